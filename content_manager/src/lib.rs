@@ -49,13 +49,17 @@ pub struct ContentManager<Conn: Connection> {
 }
 
 impl ContentManager<SqliteConnection> {
-  pub fn new(path: &str) -> ContentManager<SqliteConnection> {
-    let conn = SqliteConnection::establish(path).unwrap();
-    create_tables(&conn);
+  // Opens the database at path, the tables are only created along with the database file
+  pub fn new(path: &str) -> Result<ContentManager<SqliteConnection>, ConnectionError> {
+    let exists = std::path::Path::new(path).is_file();
+    let conn = SqliteConnection::establish(path)?;
+    if !exists {
+      create_tables(&conn).map_err(ConnectionError::CouldntSetupConfiguration)?;
+    }
 
-    ContentManager{
+    Ok(ContentManager{
       conn,
-    }
+    })
   }
 
   pub fn get_sites(&self) -> Vec<models::Site> {
@@ -69,6 +73,12 @@ impl ContentManager<SqliteConnection> {
     insert_into(site::table).values(&new_site).execute(&self.conn)
   }
 
+  // Removes the site along with its content, peer and optional file rows,
+  // returns the number of site rows removed
+  pub fn remove_site(&self, address: &str) -> Result<usize, diesel::result::Error> {
+    self.conn.transaction(|| remove_site(&self.conn, address))
+  }
+
   pub fn add_content(&self, content: zerucontent::Content) -> Result<usize, diesel::result::Error> {
     let new_content = NewContent::from(content);
     insert_into(content::table).values(&new_content).execute(&self.conn)
@@ -80,6 +90,20 @@ impl ContentManager<SqliteConnection> {
   }
 }
 
+pub fn remove_site(conn: &SqliteConnection, address: &str) -> Result<usize, diesel::result::Error> {
+  use diesel::delete;
+  let site_ids = site::table
+    .filter(site::address.eq(address))
+    .select(site::site_id)
+    .load::<i32>(conn)?;
+  for site_id in site_ids {
+    delete(content::table.filter(content::site_id.eq(site_id))).execute(conn)?;
+    delete(peer::table.filter(peer::site_id.eq(site_id))).execute(conn)?;
+    delete(file_optional::table.filter(file_optional::site_id.eq(site_id))).execute(conn)?;
+  }
+  delete(site::table.filter(site::address.eq(address))).execute(conn)
+}
+
 pub fn get_sites(conn: &SqliteConnection) -> Vec<models::Site> {
   let results = site::table.limit(1).load::<Site>(conn).unwrap();
   results
@@ -122,6 +146,35 @@ mod tests {
     assert!(results[0].address == String::from("Test"));
   }
 
+  #[test]
+  fn site_removal() {
+    use diesel::sql_query;
+    let conn = initialize_database();
+    for address in ["Test", "Other"].iter() {
+      let new_site = NewSite{
+        address: *address,
+      };
+      diesel::insert_into(site::table).values(&new_site).execute(&conn).unwrap();
+    }
+    let mut content = NewContent::from(zerucontent::Content::default());
+    content.site_id = 1;
+    diesel::insert_into(content::table).values(&content).execute(&conn).unwrap();
+    content.site_id = 2;
+    diesel::insert_into(content::table).values(&content).execute(&conn).unwrap();
+    sql_query("INSERT INTO peer VALUES (1, '127.0.0.1', 15441, NULL, 0, 0, 0);").execute(&conn).unwrap();
+
+    assert_eq!(remove_site(&conn, "Test").unwrap(), 1);
+    let sites = site::table.load::<Site>(&conn).unwrap();
+    assert!(sites.len() == 1);
+    assert!(sites[0].address == String::from("Other"));
+    let contents = content::table.load::<Content>(&conn).unwrap();
+    assert!(contents.len() == 1);
+    assert!(contents[0].site_id == 2);
+    let peers: i64 = peer::table.count().get_result(&conn).unwrap();
+    assert!(peers == 0);
+    assert_eq!(remove_site(&conn, "Test").unwrap(), 0);
+  }
+
   #[test]
   fn content_insertion() {
     let conn = initialize_database();
@@ -18,6 +18,18 @@ table! {
 	}
 }
 
+table! {
+	peer (site_id, address, port) {
+		site_id -> Integer,
+		address -> Text,
+		port -> Integer,
+		hashfield -> Nullable<Binary>,
+		reputation -> Integer,
+		time_added -> Integer,
+		time_found -> Integer,
+	}
+}
+
 table! {
 	file_optional (file_id) {
 		file_id -> Integer,
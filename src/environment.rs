@@ -1,4 +1,5 @@
 use clap::{App, Arg, SubCommand};
+use rand::Rng;
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -29,7 +30,29 @@ pub struct Environment {
 	pub broadcast_port: usize,
 	pub ui_ip: String,
 	pub ui_port: usize,
+	pub fileserver_ip: String,
+	pub fileserver_port: usize,
+	pub connected_limit: usize,
+	pub global_connected_limit: usize,
+	// Seconds after which idle peer connections are closed
+	pub connection_idle_timeout: usize,
+	// Number of peers a site is published to
+	pub publish_limit: usize,
+	// Default size limit of a site in MB
+	pub size_limit: usize,
+	// Maximum size of a content.json file in MB
+	pub file_size_limit: usize,
 	pub trackers: Vec<String>,
+	// Subcommand to run instead of starting the client
+	pub command: Option<Command>,
+}
+
+#[derive(Debug)]
+pub enum Command {
+	SiteVerify {
+		address: String,
+		delete_unlisted: bool,
+	},
 }
 
 #[derive(Debug)]
@@ -156,6 +179,14 @@ pub fn get_env() -> Result<Environment, Error> {
 				.long("global_connected_limit")
 				.default_value("512")
 				.help("Max connections"),
+			Arg::with_name("CONNECTION_IDLE_TIMEOUT")
+				.long("connection_idle_timeout")
+				.default_value("120")
+				.help("Seconds after which idle peer connections are closed"),
+			Arg::with_name("PUBLISH_LIMIT")
+				.long("publish_limit")
+				.default_value("5")
+				.help("Number of peers a site is published to"),
 			Arg::with_name("FILESERVER_IP")
 				.long("fileserver_ip")
 				.default_value("*")
@@ -192,7 +223,16 @@ pub fn get_env() -> Result<Environment, Error> {
 			SubCommand::with_name("siteDownload"),
 			SubCommand::with_name("siteSign"),
 			SubCommand::with_name("sitePublish"),
-			SubCommand::with_name("siteVerify"),
+			SubCommand::with_name("siteVerify")
+				.about("Verify the files of a site and mark the bad ones to be downloaded again")
+				.args(&[
+					Arg::with_name("ADDRESS")
+						.required(true)
+						.help("Address of the site to verify"),
+					Arg::with_name("DELETE_UNLISTED")
+						.long("delete_unlisted")
+						.help("Delete the files not listed in any content.json file"),
+				]),
 			SubCommand::with_name("siteCmd"),
 			SubCommand::with_name("dbRebuild"),
 			SubCommand::with_name("dbQuery"),
@@ -206,12 +246,62 @@ pub fn get_env() -> Result<Environment, Error> {
 	let ui_ip = matches.value_of("UI_IP").unwrap();
 	let ui_port: usize = matches.value_of("UI_PORT").unwrap().parse()?;
 	let broadcast_port: usize = matches.value_of("BROADCAST_PORT").unwrap().parse()?;
+	let fileserver_ip = match matches.value_of("FILESERVER_IP").unwrap() {
+		"*" => "0.0.0.0",
+		ip => ip,
+	};
+	let fileserver_port = get_random_port(matches.value_of("FILESERVER_PORT_RANGE").unwrap())?;
+	let connected_limit: usize = matches.value_of("CONNECTED_LIMIT").unwrap().parse()?;
+	let global_connected_limit: usize = matches
+		.value_of("GLOBAL_CONNECTED_LIMIT")
+		.unwrap()
+		.parse()?;
+	let connection_idle_timeout: usize = matches
+		.value_of("CONNECTION_IDLE_TIMEOUT")
+		.unwrap()
+		.parse()?;
+	let publish_limit: usize = matches.value_of("PUBLISH_LIMIT").unwrap().parse()?;
+	let size_limit: usize = matches.value_of("SIZE_LIMIT").unwrap().parse()?;
+	let file_size_limit: usize = matches.value_of("FILE_SIZE_LIMIT").unwrap().parse()?;
+	let command = match matches.subcommand() {
+		("siteVerify", Some(args)) => Some(Command::SiteVerify {
+			address: String::from(args.value_of("ADDRESS").unwrap()),
+			delete_unlisted: args.is_present("DELETE_UNLISTED"),
+		}),
+		_ => None,
+	};
 	let env = Environment {
 		data_path,
 		broadcast_port,
 		ui_ip: String::from(ui_ip),
 		ui_port,
+		fileserver_ip: String::from(fileserver_ip),
+		fileserver_port,
+		connected_limit,
+		global_connected_limit,
+		connection_idle_timeout,
+		publish_limit,
+		size_limit,
+		file_size_limit,
 		trackers: TRACKERS.iter().map(|s| String::from(*s)).collect(),
+		command,
 	};
 	Ok(env)
 }
+
+// Picks a random port from a range formatted as "start-end"
+fn get_random_port(range: &str) -> Result<usize, Error> {
+	let mut parts = range.split('-');
+	let start: usize = match parts.next() {
+		Some(s) => s.parse()?,
+		None => return Err(Error::from_str("FILESERVER_PORT_RANGE is malformed")),
+	};
+	let end: usize = match parts.next() {
+		Some(s) => s.parse()?,
+		None => return Ok(start),
+	};
+	if end <= start {
+		return Ok(start);
+	}
+	Ok(rand::thread_rng().gen_range(start..end))
+}
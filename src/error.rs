@@ -10,6 +10,12 @@ pub enum Error {
 	MsgPackEncoding,
 	MsgPackDecoding(rmp_serde::decode::Error),
 	MailboxError,
+	CryptError(String),
+	Timeout,
+	ConnectionLimit,
+	ProxyError(String),
+	InvalidContent(String),
+	SizeLimit,
 }
 
 impl From<reqwest::Error> for Error {
@@ -60,6 +66,18 @@ impl From<actix::MailboxError> for Error {
 	}
 }
 
+impl From<openssl::error::ErrorStack> for Error {
+	fn from(error: openssl::error::ErrorStack) -> Error {
+		Error::CryptError(error.to_string())
+	}
+}
+
+impl<S: std::fmt::Debug> From<openssl::ssl::HandshakeError<S>> for Error {
+	fn from(error: openssl::ssl::HandshakeError<S>) -> Error {
+		Error::CryptError(error.to_string())
+	}
+}
+
 impl From<zeronet_protocol::Error> for Error {
 	fn from(error: zeronet_protocol::Error) -> Error {
 		println!("{:?}", error);
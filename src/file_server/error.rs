@@ -0,0 +1,43 @@
+use derive_more::Display;
+
+#[derive(Debug, Display)]
+pub enum Error {
+	ErrorBindingSocket,
+	ConnectionError(String),
+	ErrorDecodingMessagePack(String),
+	ErrorEncodingMessagePack(String),
+	ErrorReadingFile(String),
+	InvalidInnerPath,
+	InvalidLocation,
+	MailboxError,
+}
+
+impl From<std::io::Error> for Error {
+	fn from(err: std::io::Error) -> Error {
+		Error::ErrorReadingFile(err.to_string())
+	}
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+	fn from(err: rmp_serde::decode::Error) -> Error {
+		Error::ErrorDecodingMessagePack(err.to_string())
+	}
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+	fn from(err: rmp_serde::encode::Error) -> Error {
+		Error::ErrorEncodingMessagePack(err.to_string())
+	}
+}
+
+impl From<actix::MailboxError> for Error {
+	fn from(_: actix::MailboxError) -> Error {
+		Error::MailboxError
+	}
+}
+
+impl From<crate::error::Error> for Error {
+	fn from(err: crate::error::Error) -> Error {
+		Error::ConnectionError(err.to_string())
+	}
+}
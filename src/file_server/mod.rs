@@ -0,0 +1,412 @@
+mod error;
+
+use crate::environment::Environment;
+use crate::optional_files::hashfield::Hashfield;
+use crate::peer::connections::{Connection, ConnectionLimit, ConnectionSlot, TcpConnection};
+use crate::peer::crypt::{create_acceptor, CRYPT_TLS_RSA};
+use crate::peer::message::*;
+use crate::peer::pack::PackedPeers;
+use crate::peer::peer_manager::{update_peers, PeerManager};
+use crate::site::address::Address;
+use crate::site::site_manager::{Lookup, SiteManager, SiteServedRequest};
+use crate::site::{
+	ContentUpdate, FindHashIdsRequest, ModifiedContentsRequest, SetPeerHashfield, Site,
+	SiteHashfieldRequest, SitePeersRequest,
+};
+use crate::util::is_valid_inner_path;
+use actix::Addr;
+use futures::executor::block_on;
+use log::*;
+use openssl::ssl::SslAcceptor;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use error::Error;
+
+// Maximum number of peers sent in a pex response
+const PEX_LIMIT: usize = 30;
+
+// start_file_server binds the file server and accepts connections in a new thread,
+// every accepted connection takes a slot from the shared connection limit
+pub fn start_file_server(
+	env: &Environment,
+	site_manager: Addr<SiteManager>,
+	peer_manager: Addr<PeerManager>,
+	connection_limit: ConnectionLimit,
+) -> Result<(), Error> {
+	let address = format!("{}:{}", env.fileserver_ip, env.fileserver_port);
+	let listener = match TcpListener::bind(&address) {
+		Ok(l) => l,
+		Err(err) => {
+			error!("Could not bind file server to {}: {:?}", address, err);
+			return Err(Error::ErrorBindingSocket);
+		}
+	};
+	info!("File server listening on {}", address);
+
+	let vec: Vec<u8> = (0..12).map(|_| rand::random::<u8>()).collect();
+	let peer_id = format!("-UT3530-{}", base64::encode(&vec));
+	let fileserver_port = env.fileserver_port;
+	let data_path = env.data_path.clone();
+	// 0 disables the timeout
+	let idle_timeout = match env.connection_idle_timeout {
+		0 => None,
+		secs => Some(Duration::from_secs(secs as u64)),
+	};
+	let acceptor = match create_acceptor(&data_path) {
+		Ok(acceptor) => Some(Arc::new(acceptor)),
+		Err(err) => {
+			error!("Could not set up TLS, only accepting plaintext: {:?}", err);
+			None
+		}
+	};
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			let socket = match stream {
+				Ok(s) => s,
+				Err(err) => {
+					error!("Error accepting connection: {:?}", err);
+					continue;
+				}
+			};
+			let slot = match connection_limit.acquire() {
+				Some(slot) => slot,
+				None => {
+					trace!(
+						"Connection limit reached, refusing connection from {:?}",
+						socket.peer_addr()
+					);
+					continue;
+				}
+			};
+			// Peers that stop sending requests are disconnected
+			if let Err(err) = socket.set_read_timeout(idle_timeout) {
+				error!("Error setting read timeout: {:?}", err);
+				continue;
+			}
+			let data_path = data_path.clone();
+			let site_manager = site_manager.clone();
+			let peer_manager = peer_manager.clone();
+			let peer_id = peer_id.clone();
+			let acceptor = acceptor.clone();
+			std::thread::spawn(move || {
+				let connection = match TcpConnection::accept(socket, acceptor.as_deref()) {
+					Ok(c) => c,
+					Err(err) => {
+						trace!("Error accepting connection: {:?}", err);
+						return;
+					}
+				};
+				let mut connection = FileServerConnection {
+					connection,
+					data_path,
+					site_manager,
+					peer_manager,
+					peer_id,
+					fileserver_port,
+					peer_address: None,
+					acceptor,
+					_slot: slot,
+				};
+				connection.serve();
+			});
+		}
+	});
+
+	Ok(())
+}
+
+struct FileServerConnection {
+	connection: TcpConnection,
+	data_path: PathBuf,
+	site_manager: Addr<SiteManager>,
+	peer_manager: Addr<PeerManager>,
+	peer_id: String,
+	fileserver_port: usize,
+	// Address the other side serves files on, known after its handshake
+	peer_address: Option<String>,
+	// None if TLS could not be set up
+	acceptor: Option<Arc<SslAcceptor>>,
+	// Released when the connection is closed
+	_slot: ConnectionSlot,
+}
+
+impl FileServerConnection {
+	fn serve(&mut self) {
+		let peer_ip = self.connection.peer_ip();
+		trace!("Accepted connection from {}", peer_ip);
+		loop {
+			let request: Request = match self.connection.recv() {
+				Ok(r) => r,
+				Err(err) => {
+					trace!("Closing connection from {}: {:?}", peer_ip, err);
+					return;
+				}
+			};
+			if let Err(err) = self.handle_request(request) {
+				error!("Error handling request from {}: {:?}", peer_ip, err);
+				return;
+			}
+		}
+	}
+	fn send<T: DeserializeOwned + Serialize>(&mut self, msg: T) -> Result<(), Error> {
+		self.connection.send(msg)?;
+		Ok(())
+	}
+	fn handle_request(&mut self, req: Request) -> Result<(), Error> {
+		trace!("Received {} request", req.cmd);
+		match req.cmd.as_str() {
+			"handshake" => self.handle_handshake(&req),
+			"ping" => self.send(PongResponse::new(req.req_id)),
+			"getFile" => self.handle_get_file(&req),
+			"streamFile" => self.handle_stream_file(&req),
+			"pex" => self.handle_pex(&req),
+			"getHashfield" => self.handle_get_hashfield(&req),
+			"setHashfield" => self.handle_set_hashfield(&req),
+			"findHashIds" => self.handle_find_hash_ids(&req),
+			"listModified" => self.handle_list_modified(&req),
+			"update" => self.handle_update(&req),
+			_ => {
+				warn!("Unhandled file server command: {}", req.cmd);
+				self.send(ErrorResponse::new(req.req_id, "Unknown cmd"))
+			}
+		}
+	}
+	fn handle_handshake(&mut self, req: &Request) -> Result<(), Error> {
+		let mut resp = HandshakeResponse::new(req.req_id);
+		resp.fileserver_port = self.fileserver_port;
+		resp.peer_id = self.peer_id.clone();
+		resp.target_ip = self.connection.peer_ip();
+		// Only negotiate TLS if the peer didn't start with it already
+		let negotiate = self.connection.crypt().is_none()
+			&& self.acceptor.is_some()
+			&& req
+				.params
+				.crypt_supported
+				.iter()
+				.any(|c| c == CRYPT_TLS_RSA);
+		if self.acceptor.is_some() {
+			resp.crypt_supported = vec![String::from(CRYPT_TLS_RSA)];
+		}
+		resp.crypt = match negotiate {
+			true => Some(String::from(CRYPT_TLS_RSA)),
+			false => self.connection.crypt(),
+		};
+		if let Ok(ip) = resp.target_ip.parse::<IpAddr>() {
+			if req.params.fileserver_port != 0 {
+				let address = SocketAddr::new(ip, req.params.fileserver_port as u16);
+				self.peer_address = Some(address.to_string());
+			}
+		}
+		self.send(resp)?;
+		if let (true, Some(acceptor)) = (negotiate, &self.acceptor) {
+			self.connection.accept_tls(acceptor)?;
+		}
+		Ok(())
+	}
+	fn is_serving(&self, site: &str) -> bool {
+		let address = match Address::from_str(site) {
+			Ok(a) => a,
+			Err(_) => return false,
+		};
+		match block_on(self.site_manager.send(SiteServedRequest { address })) {
+			Ok(Ok(serving)) => serving,
+			_ => false,
+		}
+	}
+	// Opens the file positioned at `location`, returns it with the total size of the file
+	fn open_file(
+		&self,
+		site: &str,
+		inner_path: &str,
+		location: usize,
+	) -> Result<(File, usize), Error> {
+		if !is_valid_inner_path(inner_path) {
+			return Err(Error::InvalidInnerPath);
+		}
+		let path = self.data_path.join(site).join(inner_path);
+		let mut file = File::open(&path)?;
+		let size = file.metadata()?.len() as usize;
+		if location > size {
+			return Err(Error::InvalidLocation);
+		}
+		file.seek(SeekFrom::Start(location as u64))?;
+		Ok((file, size))
+	}
+	// Checks the request and opens the requested file, responding with
+	// an error to the peer if anything is wrong with the request
+	fn open_requested(&mut self, req: &Request) -> Result<Option<(File, usize)>, Error> {
+		let params = &req.params;
+		if !self.is_serving(&params.site) {
+			self.send(ErrorResponse::new(req.req_id, "Unknown site"))?;
+			return Ok(None);
+		}
+		let (file, size) = match self.open_file(&params.site, &params.inner_path, params.location) {
+			Ok(r) => r,
+			Err(err) => {
+				trace!(
+					"Could not read zero://{}/{}: {:?}",
+					params.site,
+					params.inner_path,
+					err
+				);
+				self.send(ErrorResponse::new(req.req_id, "File read error"))?;
+				return Ok(None);
+			}
+		};
+		if params.file_size != 0 && params.file_size != size {
+			self.send(ErrorResponse::new(req.req_id, "File size does not match"))?;
+			return Ok(None);
+		}
+		Ok(Some((file, size)))
+	}
+	fn handle_get_file(&mut self, req: &Request) -> Result<(), Error> {
+		let (file, size) = match self.open_requested(req)? {
+			Some(f) => f,
+			None => return Ok(()),
+		};
+		let mut buf = Vec::new();
+		file.take(FILE_BUFF as u64).read_to_end(&mut buf)?;
+		let location = req.params.location + buf.len();
+		self.send(GetFileResponse::new(req.req_id, buf, location, size))
+	}
+	fn handle_stream_file(&mut self, req: &Request) -> Result<(), Error> {
+		let (mut file, size) = match self.open_requested(req)? {
+			Some(f) => f,
+			None => return Ok(()),
+		};
+		let read_bytes = match req.params.read_bytes {
+			0 => FILE_BUFF,
+			n => n,
+		};
+		let stream_bytes = std::cmp::min(read_bytes, size - req.params.location);
+		let location = req.params.location + stream_bytes;
+		self.send(StreamFileResponse::new(
+			req.req_id,
+			stream_bytes,
+			location,
+			size,
+		))?;
+		self.connection.send_rawfile(&mut file, stream_bytes)?;
+		Ok(())
+	}
+	// Address we serve files on, as the other side reached us
+	fn my_address(&self) -> Option<String> {
+		let ip = self.connection.local_ip()?;
+		Some(SocketAddr::new(ip, self.fileserver_port as u16).to_string())
+	}
+	fn site_addr(&self, site: &str) -> Result<Addr<Site>, Error> {
+		let address = Address::from_str(site)?;
+		let (_, site_addr) = block_on(self.site_manager.send(Lookup::Address(address)))??;
+		Ok(site_addr)
+	}
+	fn handle_pex(&mut self, req: &Request) -> Result<(), Error> {
+		let params = &req.params;
+		if !self.is_serving(&params.site) {
+			return self.send(ErrorResponse::new(req.req_id, "Unknown site"));
+		}
+		let address = Address::from_str(&params.site)?;
+		let received = params.packed_peers().unpack();
+		update_peers(&self.peer_manager, &received, &address);
+
+		let site_addr = self.site_addr(&params.site)?;
+		let mut peers = block_on(site_addr.send(SitePeersRequest {}))??;
+		peers.retain(|p| !received.contains(p));
+		peers.truncate(std::cmp::min(params.need, PEX_LIMIT));
+		self.send(PexResponse::new(req.req_id, PackedPeers::pack(&peers)))
+	}
+	fn handle_list_modified(&mut self, req: &Request) -> Result<(), Error> {
+		if !self.is_serving(&req.params.site) {
+			return self.send(ErrorResponse::new(req.req_id, "Unknown site"));
+		}
+		let site_addr = self.site_addr(&req.params.site)?;
+		let msg = ModifiedContentsRequest {
+			since: req.params.since,
+		};
+		let modified_files = block_on(site_addr.send(msg))??;
+		self.send(ListModifiedResponse::new(req.req_id, modified_files))
+	}
+	fn handle_update(&mut self, req: &Request) -> Result<(), Error> {
+		let params = &req.params;
+		if !self.is_serving(&params.site) {
+			return self.send(ErrorResponse::new(req.req_id, "Unknown site"));
+		}
+		if !params.inner_path.ends_with("content.json") {
+			return self.send(ErrorResponse::new(
+				req.req_id,
+				"Only content.json update allowed",
+			));
+		}
+		// The sender has the site, so it's a peer of it
+		if let Some(address) = &self.peer_address {
+			let site = Address::from_str(&params.site)?;
+			update_peers(&self.peer_manager, &[address.clone()], &site);
+		}
+		let site_addr = self.site_addr(&params.site)?;
+		let msg = ContentUpdate {
+			inner_path: params.inner_path.clone(),
+			body: params.body.clone(),
+			diffs: params.diffs.clone(),
+			sender: self.peer_address.clone(),
+		};
+		match block_on(site_addr.send(msg))? {
+			Ok(true) => {
+				let ok = format!("Thanks, file {} updated!", params.inner_path);
+				self.send(OkResponse::new(req.req_id, &ok))
+			}
+			Ok(false) => self.send(OkResponse::new(req.req_id, "File not changed")),
+			Err(err) => {
+				trace!(
+					"Invalid update of zero://{}/{}: {:?}",
+					params.site,
+					params.inner_path,
+					err
+				);
+				self.send(ErrorResponse::new(req.req_id, "File invalid"))
+			}
+		}
+	}
+	fn handle_get_hashfield(&mut self, req: &Request) -> Result<(), Error> {
+		if !self.is_serving(&req.params.site) {
+			return self.send(ErrorResponse::new(req.req_id, "Unknown site"));
+		}
+		let site_addr = self.site_addr(&req.params.site)?;
+		let hashfield = block_on(site_addr.send(SiteHashfieldRequest {}))??;
+		self.send(HashfieldResponse::new(req.req_id, hashfield.to_bytes()))
+	}
+	fn handle_set_hashfield(&mut self, req: &Request) -> Result<(), Error> {
+		if !self.is_serving(&req.params.site) {
+			return self.send(ErrorResponse::new(req.req_id, "Unknown site"));
+		}
+		let address = match &self.peer_address {
+			Some(address) => address.clone(),
+			None => return self.send(ErrorResponse::new(req.req_id, "Unknown peer")),
+		};
+		let site_addr = self.site_addr(&req.params.site)?;
+		let msg = SetPeerHashfield {
+			address,
+			hashfield: Hashfield::from_bytes(&req.params.hashfield_raw),
+		};
+		block_on(site_addr.send(msg))??;
+		self.send(OkResponse::new(req.req_id, "Updated"))
+	}
+	fn handle_find_hash_ids(&mut self, req: &Request) -> Result<(), Error> {
+		if !self.is_serving(&req.params.site) {
+			return self.send(ErrorResponse::new(req.req_id, "Unknown site"));
+		}
+		let site_addr = self.site_addr(&req.params.site)?;
+		let msg = FindHashIdsRequest {
+			hash_ids: req.params.hash_ids.clone(),
+			my_address: self.my_address(),
+		};
+		let found = block_on(site_addr.send(msg))??;
+		self.send(FindHashIdsResponse::new(req.req_id, found))
+	}
+}
@@ -1,5 +1,6 @@
 mod environment;
 mod error;
+mod file_server;
 mod influx_logger;
 mod local_discovery;
 mod optional_files;
@@ -14,14 +15,16 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::Path;
+use std::str::FromStr;
 
 use serde_json;
-use std::str::FromStr;
 use zerucrypt;
 
+use file_server::start_file_server;
 use futures::executor::block_on;
 use local_discovery::start_local_discovery;
 use log::*;
+use peer::connections::ConnectionLimit;
 use peer::peer_manager::start_peer_manager;
 use pretty_env_logger;
 use site::site_manager::start_site_manager;
@@ -40,6 +43,10 @@ fn main() {
 		Err(err) => panic!("{:?}", err),
 	};
 
+	if let Some(command) = &env.command {
+		std::process::exit(run_command(&env, command));
+	}
+
 	if false {
 		let punch = upnp_brunch::UPnPBrunch::new()
 			.unwrap()
@@ -61,41 +68,36 @@ fn main() {
 
 	let site_manager_addr = start_site_manager(&env).unwrap();
 	let user_manager_addr = start_user_manager(&env).unwrap();
-	let peer_manager_addr = start_peer_manager(site_manager_addr.clone()).unwrap();
-	// TODO: Spawn tracker manager in thread
-	// let tracker_manager_addr = start_tracker_manager(&env).unwrap();
-	let mut tracker_manager = crate::tracker::TrackerManager::new();
-	for tracker in &env.trackers {
-		tracker_manager.add_tracker(&tracker);
-	}
-
-	let db = content_manager::ContentManager::new("./data/content.db");
-
-	db.add_site(String::from("1HeLLo4uzjaLetFx6NH3PMwFP3qbRbTf3D"));
-	db.add_site(String::from("1MeFqFfFFGQfa1J3gJyYYUvb5Lksczq7nH"));
-	db.add_site(String::from("1TaLkFrMwvbNsooF4ioKAY9EuxTBTjipT"));
-	db.add_site(String::from("1DocsYf2tZVVMEMJFHiDsppmFicZCWkVv1"));
-
-	let sites = db.get_sites();
-	let mut addresses = vec![];
-	info!("Loaded {} sites from database", sites.len());
-	for site in sites {
-		if let Ok(address) = crate::site::address::Address::from_str(&site.address) {
-			tracker_manager.announce(&address);
-			addresses.push(address.get_address_hash());
-			site_manager_addr.do_send(crate::site::site_manager::Lookup::Address(address));
-		}
-	}
+	// Outgoing and incoming connections count towards the same limit
+	let connection_limit = ConnectionLimit::new(env.global_connected_limit);
+	let peer_manager_addr =
+		start_peer_manager(&env, site_manager_addr.clone(), connection_limit.clone()).unwrap();
+
+	// Sites are started and announced by the site manager
+	let sites = match block_on(site_manager_addr.send(site::site_manager::SiteListRequest {})) {
+		Ok(Ok(sites)) => sites,
+		_ => vec![],
+	};
+	info!("Serving {} sites", sites.len());
 	let message = peer::peer_manager::UpdatePeer {
-		sites: addresses,
+		sites: sites.into_iter().map(|site| site.into_vec()).collect(),
 		address: zeronet_protocol::Address::parse(format!("{}:{}", "127.0.0.1", 11692)).unwrap(),
 		peer_id: String::from("-UT3530-RB5sGEcwouRReZgY"),
 	};
 	peer_manager_addr.do_send(message);
 
-	let res = start_local_discovery(site_manager_addr.clone(), peer_manager_addr);
+	let res = start_local_discovery(site_manager_addr.clone(), peer_manager_addr.clone());
 	info!("{:?}", res);
 
+	if let Err(err) = start_file_server(
+		&env,
+		site_manager_addr.clone(),
+		peer_manager_addr,
+		connection_limit,
+	) {
+		error!("Could not start file server: {:?}", err);
+	}
+
 	info!("Starting zerunet server.");
 	std::thread::spawn(move || {
 		let system = actix::System::new("Server system");
@@ -162,3 +164,39 @@ fn main() {
 		Err(_) => error!("Signature mismatch!"),
 	}
 }
+
+// Run a subcommand, returning the exit code
+fn run_command(env: &environment::Environment, command: &environment::Command) -> i32 {
+	match command {
+		environment::Command::SiteVerify {
+			address,
+			delete_unlisted,
+		} => {
+			let address = match site::address::Address::from_str(address) {
+				Ok(address) => address,
+				Err(_) => {
+					error!("Invalid site address {}", address);
+					return 2;
+				}
+			};
+			match site::site_manager::verify_site(env, address, *delete_unlisted) {
+				Ok(report) => {
+					// The report is printed as JSON, so it can be picked up by monitoring
+					match serde_json::to_string_pretty(&report) {
+						Ok(json) => println!("{}", json),
+						Err(err) => error!("{:?}", err),
+					}
+					if report.is_ok() {
+						0
+					} else {
+						1
+					}
+				}
+				Err(err) => {
+					error!("Could not verify site: {:?}", err);
+					2
+				}
+			}
+		}
+	}
+}
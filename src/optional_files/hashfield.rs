@@ -0,0 +1,75 @@
+use std::collections::BTreeSet;
+
+// Hash ids are the first 2 bytes of an optional file's sha512
+pub fn hash_id(sha512: &str) -> Option<u16> {
+	u16::from_str_radix(sha512.get(0..4)?, 16).ok()
+}
+
+// Set of hash ids of the optional files a peer has,
+// exchanged as a packed array of native (little endian) u16s
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Hashfield {
+	hash_ids: BTreeSet<u16>,
+}
+
+impl Hashfield {
+	pub fn new() -> Hashfield {
+		Hashfield::default()
+	}
+	pub fn from_bytes(bytes: &[u8]) -> Hashfield {
+		Hashfield {
+			hash_ids: bytes
+				.chunks_exact(2)
+				.map(|b| u16::from_le_bytes([b[0], b[1]]))
+				.collect(),
+		}
+	}
+	pub fn to_bytes(&self) -> Vec<u8> {
+		self
+			.hash_ids
+			.iter()
+			.flat_map(|id| id.to_le_bytes().to_vec())
+			.collect()
+	}
+	// Returns true if the hash id was not in the hashfield yet
+	pub fn add(&mut self, hash_id: u16) -> bool {
+		self.hash_ids.insert(hash_id)
+	}
+	// Returns true if the hash id was in the hashfield
+	pub fn remove(&mut self, hash_id: u16) -> bool {
+		self.hash_ids.remove(&hash_id)
+	}
+	pub fn contains(&self, hash_id: u16) -> bool {
+		self.hash_ids.contains(&hash_id)
+	}
+	pub fn len(&self) -> usize {
+		self.hash_ids.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.hash_ids.is_empty()
+	}
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_hash_id() {
+		let sha512 = "4e37699bd5336b9c33ce86a3eb73b82e87460535793401874a653afeddefee59";
+		assert_eq!(hash_id(sha512), Some(0x4e37));
+		assert_eq!(hash_id("4e"), None);
+	}
+
+	#[test]
+	fn test_serialization() {
+		let mut hashfield = Hashfield::new();
+		assert!(hashfield.add(0x4e37));
+		assert!(hashfield.add(0x0102));
+		assert!(!hashfield.add(0x0102));
+		let bytes = hashfield.to_bytes();
+		assert_eq!(bytes, vec![0x02, 0x01, 0x37, 0x4e]);
+		assert_eq!(Hashfield::from_bytes(&bytes), hashfield);
+	}
+}
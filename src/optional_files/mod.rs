@@ -1,3 +1,5 @@
+pub mod hashfield;
+
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -1,14 +1,28 @@
-use super::PeerMessage;
+use super::crypt::{Stream, CRYPT_TLS_RSA, TLS_HANDSHAKE_BYTE};
+use super::message::{ErrorResponse, HandshakeResponse, PongResponse, Request, REV};
+use super::socks;
 use crate::error::Error;
+use actix::clock::delay_for;
+use futures::channel::oneshot;
+use futures::future::{select, Either};
 use log::*;
+use openssl::ssl::SslAcceptor;
+use rmp::Marker;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::collections::BTreeMap;
-use std::io::Write;
-use std::net::TcpStream;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+// How long we wait for a peer, or its proxy, to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a blocking read waits for the peer
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 pub enum PeerAddress {
 	IPV4(String, usize),
@@ -21,21 +35,19 @@ pub enum PeerAddress {
 pub trait Connection<T: DeserializeOwned + Serialize> {
 	// Send data to connection
 	fn send(&mut self, message: T) -> Result<(), Error>;
-	// Stream file to connection without msgpacking
-	// fn send_rawfile(&self, file_path: String, read_bytes: usize) -> Result<(),()>;
-	// TODO: Split up PeerMessage into Request and Response types? Or just have this one use send?
+	// Send data to connection and wait for the reply
 	fn request(&mut self, message: T) -> Result<T, Error>;
 	// fn ping(&self) -> Result<(),()>;
 	fn recv(&mut self) -> Result<T, Error>;
 }
 
 impl PeerAddress {
-	pub fn connect(&self) -> Result<Box<dyn Connection<PeerMessage>>, ()> {
+	pub fn connect(&self) -> Result<TcpConnection, Error> {
 		match self {
 			PeerAddress::IPV4(ip, port) | PeerAddress::IPV6(ip, port) => {
-				return Ok(Box::new(TcpConnection::connect(&ip, *port)?))
+				return TcpConnection::connect(&format!("{}:{}", ip, port))
 			}
-			_ => return Err(()),
+			_ => return Err(Error::MissingError),
 		}
 	}
 }
@@ -58,12 +70,12 @@ impl Default for Handshake {
 	fn default() -> Self {
 		Handshake {
 			crypt: None,
-			crypt_supported: vec![],
+			crypt_supported: vec![String::from(CRYPT_TLS_RSA)],
 			fileserver_port: 0,
 			protocol: "v2".to_string(),
 			port_opened: false,
 			peer_id: "-ZHS".to_string(),
-			rev: 0,
+			rev: REV,
 			target_ip: "127.0.0.1".to_string(),
 			version: "0.7.1".to_string(),
 			zerunet: true,
@@ -72,58 +84,612 @@ impl Default for Handshake {
 }
 
 pub struct TcpConnection {
-	socket: TcpStream,
+	socket: Stream,
+	next_req_id: usize,
+	// Handshake the other side responded with, None for incoming connections
+	pub handshake: Option<HandshakeResponse>,
 }
 
 impl TcpConnection {
-	pub fn connect(ip: &str, port: usize) -> Result<TcpConnection, ()> {
-		trace!("Connecting to {}:{}", &ip, port);
-		let socket = TcpStream::connect(format!("{}:{}", ip, port));
-		if socket.is_err() {
-			error!("Could not connect to {}:{}", ip, port);
-			return Err(());
+	// Connects and handshakes, using TLS unless the peer doesn't support it,
+	// onion and i2p peers are reached through their SOCKS5 proxy
+	pub fn connect(address: &str) -> Result<TcpConnection, Error> {
+		let mut connection = TcpConnection::open(address)?;
+		// Tor and I2P encrypt the connection already
+		if socks::proxy_for(address).is_some() {
+			connection.handshake(false)?;
+			return Ok(connection);
+		}
+		if connection.is_local() {
+			connection.handshake(true)?;
+			return Ok(connection);
+		}
+		// Try implicit TLS first, handshaking within the encrypted connection
+		match connection.socket.connect_tls() {
+			Ok(socket) => {
+				connection.socket = socket;
+				connection.handshake(false)?;
+			}
+			Err(err) => {
+				trace!("TLS connection to {} failed: {:?}", address, err);
+				connection = TcpConnection::open(address)?;
+				connection.handshake(true)?;
+			}
 		}
-		let socket = socket.unwrap();
-		socket.set_read_timeout(Some(Duration::new(60, 0))).unwrap();
-
-		let mut connection = TcpConnection { socket };
-
-		let mut handshake = PeerMessage::default();
-		handshake.cmd = "handshake".to_string();
-		handshake.params = serde_json::to_value(Handshake::default()).unwrap();
-		handshake.req_id = Some(0);
-
-		connection.request(handshake);
-
 		Ok(connection)
 	}
+	fn open(address: &str) -> Result<TcpConnection, Error> {
+		trace!("Connecting to {}", address);
+		let socket = match socks::proxy_for(address) {
+			Some(proxy) => connect_proxied(proxy, address),
+			None => connect_timeout(address),
+		};
+		let socket = match socket {
+			Ok(s) => s,
+			Err(err) => {
+				error!("Could not connect to {}: {:?}", address, err);
+				return Err(err);
+			}
+		};
+		socket.set_read_timeout(Some(READ_TIMEOUT))?;
+		Ok(TcpConnection::from_stream(socket))
+	}
+	// Sends our handshake, when negotiate is set the connection
+	// is wrapped in TLS afterwards if the peer chose to,
+	// otherwise TLS is only offered if it's already in place
+	fn handshake(&mut self, negotiate: bool) -> Result<(), Error> {
+		let crypt = self.socket.crypt();
+		let crypt_supported = if negotiate || crypt.is_some() {
+			vec![String::from(CRYPT_TLS_RSA)]
+		} else {
+			vec![]
+		};
+		let handshake = Handshake {
+			crypt,
+			crypt_supported,
+			..Handshake::default()
+		};
+		let response: HandshakeResponse = self.call("handshake", handshake)?;
+		if negotiate && response.crypt.as_deref() == Some(CRYPT_TLS_RSA) {
+			self.socket = self.socket.connect_tls()?;
+		}
+		self.handshake = Some(response);
+		Ok(())
+	}
+	// Wraps an accepted stream
+	pub fn from_stream(socket: TcpStream) -> TcpConnection {
+		TcpConnection {
+			socket: Stream::Plain(socket),
+			next_req_id: 0,
+			handshake: None,
+		}
+	}
+	// Wraps an accepted stream, accepting TLS right away
+	// if the peer starts with a TLS handshake
+	pub fn accept(socket: TcpStream, acceptor: Option<&SslAcceptor>) -> Result<TcpConnection, Error> {
+		let mut connection = TcpConnection::from_stream(socket);
+		if let Some(acceptor) = acceptor {
+			let mut first = [0u8; 1];
+			if connection.socket.tcp().peek(&mut first)? == 1 && first[0] == TLS_HANDSHAKE_BYTE {
+				connection.socket = connection.socket.accept_tls(acceptor)?;
+			}
+		}
+		Ok(connection)
+	}
+	// Wraps the connection in TLS after we've answered a handshake with a crypt
+	pub fn accept_tls(&mut self, acceptor: &SslAcceptor) -> Result<(), Error> {
+		self.socket = self.socket.accept_tls(acceptor)?;
+		Ok(())
+	}
+	pub fn crypt(&self) -> Option<String> {
+		self.socket.crypt()
+	}
+	pub fn peer_ip(&self) -> String {
+		match self.socket.tcp().peer_addr() {
+			Ok(addr) => addr.ip().to_string(),
+			Err(_) => String::new(),
+		}
+	}
+	pub fn local_ip(&self) -> Option<IpAddr> {
+		self.socket.tcp().local_addr().ok().map(|addr| addr.ip())
+	}
+	// Implicit TLS is skipped within the local network
+	fn is_local(&self) -> bool {
+		match self.socket.tcp().peer_addr().map(|addr| addr.ip()) {
+			Ok(IpAddr::V4(ip)) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+			Ok(IpAddr::V6(ip)) => ip.is_loopback(),
+			Err(_) => false,
+		}
+	}
+	pub fn supports_stream_file(&self) -> bool {
+		match &self.handshake {
+			Some(handshake) => handshake.supports_stream_file(),
+			None => false,
+		}
+	}
+	// Sends a command and waits for the response
+	pub fn call<P: Serialize, R: DeserializeOwned>(
+		&mut self,
+		cmd: &str,
+		params: P,
+	) -> Result<R, Error> {
+		let req = Request {
+			cmd: String::from(cmd),
+			req_id: self.next_req_id,
+			params,
+		};
+		self.next_req_id += 1;
+		self.write(&req)?;
+		self.read()
+	}
+	// Stream file to connection without msgpacking
+	pub fn send_rawfile(&mut self, file: &mut dyn Read, read_bytes: usize) -> Result<(), Error> {
+		let sent = std::io::copy(&mut file.take(read_bytes as u64), &mut self.socket)?;
+		trace!("Streamed {} bytes to socket.", sent);
+		Ok(())
+	}
+	fn write<T: Serialize>(&mut self, msg: &T) -> Result<(), Error> {
+		let bytes = rmp_serde::to_vec_named(msg)?;
+		self.socket.write_all(&bytes)?;
+		trace!("Wrote {} bytes to socket.", bytes.len());
+		Ok(())
+	}
+	fn read<T: DeserializeOwned>(&mut self) -> Result<T, Error> {
+		let response: Result<T, _> = rmp_serde::from_read(&mut self.socket);
+		match response {
+			Ok(msg) => Ok(msg),
+			Err(err) => {
+				error!("Encountered error receiving response {:?}", err);
+				Err(Error::MissingError)
+			}
+		}
+	}
 }
 
 impl<T: DeserializeOwned + Serialize> Connection<T> for TcpConnection {
 	fn send(&mut self, msg: T) -> Result<(), Error> {
-		let bytes = rmp_serde::to_vec_named(&msg)?;
-		let json_value: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
-		trace!("Writing {} to socket.", json_value);
-		rmp_serde::encode::write_named(&mut self.socket, &msg);
-		self.socket.write_all(&bytes)?;
-		trace!("Wrote {:?} to socket.", String::from_utf8_lossy(&bytes));
-		Ok(())
+		self.write(&msg)
 	}
 	fn request(&mut self, msg: T) -> Result<T, Error> {
-		self.send(msg)?;
-		self.recv()
+		self.write(&msg)?;
+		self.read()
 	}
 	fn recv(&mut self) -> Result<T, Error> {
-		let response: Result<T, _> = rmp_serde::from_read(&mut self.socket);
-		match response {
-			Ok(msg) => {
-				trace!("{:?}", json!(&msg));
-				Ok(msg)
+		self.read()
+	}
+}
+
+// Tries every address the name resolves to in turn
+fn connect_timeout(address: &str) -> Result<TcpStream, Error> {
+	let mut last_err = None;
+	for addr in address.to_socket_addrs()? {
+		match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+			Ok(socket) => return Ok(socket),
+			Err(err) => last_err = Some(err),
+		}
+	}
+	Err(last_err.map(Error::from).unwrap_or(Error::MissingError))
+}
+
+// Connects to the address through a SOCKS5 proxy
+fn connect_proxied(proxy: &str, address: &str) -> Result<TcpStream, Error> {
+	let mut socket = connect_timeout(proxy)?;
+	// Building a circuit to the peer can take a while
+	socket.set_read_timeout(Some(READ_TIMEOUT))?;
+	socks::connect(&mut socket, address)?;
+	Ok(socket)
+}
+
+// Response to a request on a multiplexed connection, decoded by the requester
+pub struct Response {
+	raw: Vec<u8>,
+	// Raw bytes following a streamFile response
+	pub stream: Vec<u8>,
+}
+
+impl Response {
+	pub fn decode<R: DeserializeOwned>(&self) -> Result<R, Error> {
+		Ok(rmp_serde::from_slice(&self.raw)?)
+	}
+}
+
+// Fields every message has, used to route responses to their request
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct MessageHeader {
+	cmd: String,
+	req_id: usize,
+	to: usize,
+	stream_bytes: usize,
+	error: Option<String>,
+}
+
+// Shared count of open connections, capped at a limit
+#[derive(Clone)]
+pub struct ConnectionLimit {
+	open: Arc<AtomicUsize>,
+	limit: usize,
+}
+
+impl ConnectionLimit {
+	pub fn new(limit: usize) -> ConnectionLimit {
+		ConnectionLimit {
+			open: Arc::new(AtomicUsize::new(0)),
+			limit,
+		}
+	}
+	pub fn open(&self) -> usize {
+		self.open.load(Ordering::SeqCst)
+	}
+	pub fn limit(&self) -> usize {
+		self.limit
+	}
+	// Reserves a connection, None if the limit has been reached
+	pub fn acquire(&self) -> Option<ConnectionSlot> {
+		let mut open = self.open();
+		loop {
+			if open >= self.limit {
+				return None;
 			}
-			Err(err) => {
-				error!("Encountered error receiving response {:?}", err);
-				Err(Error::MissingError)
+			match self
+				.open
+				.compare_exchange(open, open + 1, Ordering::SeqCst, Ordering::SeqCst)
+			{
+				Ok(_) => {
+					return Some(ConnectionSlot {
+						open: self.open.clone(),
+					})
+				}
+				Err(current) => open = current,
 			}
 		}
 	}
 }
+
+// A reserved connection, released when dropped
+pub struct ConnectionSlot {
+	open: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionSlot {
+	fn drop(&mut self) {
+		self.open.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+// Sender waiting for the response to a request
+type Reply = oneshot::Sender<Result<Response, Error>>;
+// Requests waiting for a response, by req_id
+type Pending = Arc<Mutex<HashMap<usize, Reply>>>;
+
+// Handle to a connection read by its own thread, which allows any number of
+// requests in flight at once, matching responses to requests by req_id
+#[derive(Clone)]
+pub struct PeerConnection {
+	inner: Arc<Inner>,
+}
+
+// State shared by the handles, the connection is shut down when the last
+// handle is dropped which stops the reader thread
+struct Inner {
+	stream: Arc<Mutex<Stream>>,
+	pending: Pending,
+	// Set by the reader thread once the connection has failed
+	closed: Arc<AtomicBool>,
+	tcp: TcpStream,
+	next_req_id: AtomicUsize,
+	supports_stream_file: bool,
+}
+
+impl Drop for Inner {
+	fn drop(&mut self) {
+		let _ = self.tcp.shutdown(Shutdown::Both);
+	}
+}
+
+impl PeerConnection {
+	// Takes over a connection that has finished its handshake,
+	// the slot is held until the connection is closed
+	pub fn start(
+		connection: TcpConnection,
+		slot: Option<ConnectionSlot>,
+	) -> Result<PeerConnection, Error> {
+		let supports_stream_file = connection.supports_stream_file();
+		let mut socket = connection.socket;
+		let tcp = socket.tcp().try_clone()?;
+		// The reader thread waits for as long as the connection is open
+		tcp.set_read_timeout(None)?;
+		let reader = tcp.try_clone()?;
+		socket.buffer_reads();
+		let stream = Arc::new(Mutex::new(socket));
+		let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+		let closed = Arc::new(AtomicBool::new(false));
+		let peer_connection = PeerConnection {
+			inner: Arc::new(Inner {
+				stream: stream.clone(),
+				pending: pending.clone(),
+				closed: closed.clone(),
+				tcp,
+				next_req_id: AtomicUsize::new(connection.next_req_id),
+				supports_stream_file,
+			}),
+		};
+		std::thread::spawn(move || {
+			if let Err(err) = read_connection(reader, &stream, &pending) {
+				trace!("Closing connection: {:?}", err);
+			}
+			let mut pending = pending.lock().unwrap();
+			closed.store(true, Ordering::SeqCst);
+			for (_, reply) in pending.drain() {
+				let _ = reply.send(Err(Error::MissingError));
+			}
+			drop(slot);
+		});
+		Ok(peer_connection)
+	}
+	pub fn is_closed(&self) -> bool {
+		self.inner.closed.load(Ordering::SeqCst)
+	}
+	pub fn supports_stream_file(&self) -> bool {
+		self.inner.supports_stream_file
+	}
+	// Sends a command, the response resolves the returned future unless it
+	// takes longer than the timeout or the connection is closed first
+	pub fn request<P: Serialize>(
+		&self,
+		cmd: &str,
+		params: P,
+		timeout: Duration,
+	) -> impl Future<Output = Result<Response, Error>> {
+		let (reply, receiver) = oneshot::channel();
+		let req = Request {
+			cmd: String::from(cmd),
+			req_id: self.inner.next_req_id.fetch_add(1, Ordering::SeqCst),
+			params,
+		};
+		let sent = self.send(&req, reply);
+		// Requests in flight keep the connection open
+		let connection = self.clone();
+		let req_id = req.req_id;
+		async move {
+			sent?;
+			match select(receiver, delay_for(timeout)).await {
+				Either::Left((Ok(result), _)) => result,
+				Either::Left((Err(_), _)) => Err(Error::MissingError),
+				Either::Right(_) => {
+					trace!("Request {} timed out", req_id);
+					connection.inner.pending.lock().unwrap().remove(&req_id);
+					Err(Error::Timeout)
+				}
+			}
+		}
+	}
+	fn send<P: Serialize>(&self, req: &Request<P>, reply: Reply) -> Result<(), Error> {
+		let bytes = rmp_serde::to_vec_named(req)?;
+		{
+			// Checked under the lock the reader thread fails requests with,
+			// so a request can't be added after the connection closed
+			let mut pending = self.inner.pending.lock().unwrap();
+			if self.is_closed() {
+				return Err(Error::MissingError);
+			}
+			pending.insert(req.req_id, reply);
+		}
+		let written = self.inner.stream.lock().unwrap().write_all(&bytes);
+		if let Err(err) = written {
+			self.inner.pending.lock().unwrap().remove(&req.req_id);
+			return Err(Error::from(err));
+		}
+		Ok(())
+	}
+}
+
+// Reads until the connection fails or is shut down, handing responses
+// to their requests, the socket is only locked while handling what was read
+fn read_connection(
+	mut reader: TcpStream,
+	stream: &Mutex<Stream>,
+	pending: &Mutex<HashMap<usize, Reply>>,
+) -> Result<(), Error> {
+	let mut buf: Vec<u8> = Vec::new();
+	let mut chunk = vec![0u8; 64 * 1024];
+	// TLS may still hold records read during the handshake
+	let mut len = 0;
+	loop {
+		let mut stream = stream.lock().unwrap();
+		stream.receive(&chunk[..len], &mut buf)?;
+		while let Some((header, response)) = next_message(&mut buf)? {
+			dispatch(&mut stream, pending, header, response)?;
+		}
+		drop(stream);
+		len = loop {
+			match reader.read(&mut chunk) {
+				Ok(0) => return Err(Error::MissingError),
+				Ok(len) => break len,
+				Err(err) if err.kind() == ErrorKind::Interrupted => {}
+				Err(err) => return Err(Error::from(err)),
+			}
+		};
+	}
+}
+
+// Hands a response to its request, pings are answered
+// and any other request is turned down
+fn dispatch(
+	stream: &mut Stream,
+	pending: &Mutex<HashMap<usize, Reply>>,
+	header: MessageHeader,
+	response: Response,
+) -> Result<(), Error> {
+	match header.cmd.as_str() {
+		"response" => {}
+		"ping" => return write_message(stream, &PongResponse::new(header.req_id)),
+		cmd => {
+			trace!("Refusing {} request on outgoing connection", cmd);
+			let error = ErrorResponse::new(header.req_id, "Unknown command");
+			return write_message(stream, &error);
+		}
+	}
+	let reply = match pending.lock().unwrap().remove(&header.to) {
+		Some(reply) => reply,
+		None => {
+			trace!("Received response to unknown request {}", header.to);
+			return Ok(());
+		}
+	};
+	let result = match header.error {
+		Some(error) => {
+			trace!("Peer responded with error: {}", error);
+			Err(Error::MissingError)
+		}
+		None => Ok(response),
+	};
+	let _ = reply.send(result);
+	Ok(())
+}
+
+fn write_message<T: Serialize>(stream: &mut Stream, msg: &T) -> Result<(), Error> {
+	let bytes = rmp_serde::to_vec_named(msg)?;
+	stream.write_all(&bytes)?;
+	Ok(())
+}
+
+// Takes the first complete message off the buffer, None if it hasn't fully arrived yet
+fn next_message(buf: &mut Vec<u8>) -> Result<Option<(MessageHeader, Response)>, Error> {
+	let len = match value_len(buf)? {
+		Some(len) => len,
+		None => return Ok(None),
+	};
+	let header: MessageHeader = rmp_serde::from_slice(&buf[..len])?;
+	if buf.len() < len + header.stream_bytes {
+		return Ok(None);
+	}
+	// Split the message off the buffer and the stream off the message,
+	// so the streamed bytes are only moved once
+	let rest = buf.split_off(len + header.stream_bytes);
+	let mut raw = std::mem::replace(buf, rest);
+	let stream = raw.split_off(len);
+	Ok(Some((header, Response { raw, stream })))
+}
+
+// Length of the msgpack value at the start of the buffer, None if it hasn't
+// fully arrived yet. Only markers and lengths are read, strings and binaries
+// are skipped, so scanning a message again as more of it arrives is cheap
+fn value_len(buf: &[u8]) -> Result<Option<usize>, Error> {
+	let mut pos = 0;
+	// Values left to scan, arrays and maps add their items
+	let mut values: usize = 1;
+	while values > 0 {
+		values -= 1;
+		let marker = match buf.get(pos) {
+			Some(byte) => Marker::from_u8(*byte),
+			None => return Ok(None),
+		};
+		pos += 1;
+		let prefix = match marker {
+			Marker::Str8 | Marker::Bin8 | Marker::Ext8 => 1,
+			Marker::Str16 | Marker::Bin16 | Marker::Ext16 | Marker::Array16 | Marker::Map16 => 2,
+			Marker::Str32 | Marker::Bin32 | Marker::Ext32 | Marker::Array32 | Marker::Map32 => 4,
+			_ => 0,
+		};
+		let len = match buf.get(pos..pos + prefix) {
+			Some(bytes) => bytes
+				.iter()
+				.fold(0usize, |len, byte| len << 8 | *byte as usize),
+			None => return Ok(None),
+		};
+		pos += prefix;
+		match marker {
+			Marker::FixPos(_) | Marker::FixNeg(_) | Marker::Null | Marker::True | Marker::False => {}
+			Marker::U8 | Marker::I8 => pos += 1,
+			Marker::U16 | Marker::I16 => pos += 2,
+			Marker::U32 | Marker::I32 | Marker::F32 => pos += 4,
+			Marker::U64 | Marker::I64 | Marker::F64 => pos += 8,
+			Marker::FixStr(len) => pos += len as usize,
+			Marker::Str8 | Marker::Str16 | Marker::Str32 => pos += len,
+			Marker::Bin8 | Marker::Bin16 | Marker::Bin32 => pos += len,
+			Marker::FixArray(len) => values += len as usize,
+			Marker::Array16 | Marker::Array32 => values += len,
+			Marker::FixMap(len) => values += 2 * len as usize,
+			Marker::Map16 | Marker::Map32 => values += 2 * len,
+			// Type byte and data
+			Marker::FixExt1 => pos += 2,
+			Marker::FixExt2 => pos += 3,
+			Marker::FixExt4 => pos += 5,
+			Marker::FixExt8 => pos += 9,
+			Marker::FixExt16 => pos += 17,
+			Marker::Ext8 | Marker::Ext16 | Marker::Ext32 => pos += 1 + len,
+			Marker::Reserved => {
+				return Err(Error::from(rmp_serde::decode::Error::TypeMismatch(
+					Marker::Reserved,
+				)))
+			}
+		}
+	}
+	if pos > buf.len() {
+		return Ok(None);
+	}
+	Ok(Some(pos))
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::super::message::{GetFileResponse, StreamFileResponse};
+	use super::*;
+
+	#[test]
+	fn test_value_len() {
+		let body = vec![7u8; 70000];
+		let msg = rmp_serde::to_vec_named(&GetFileResponse::new(1, body, 0, 70000)).unwrap();
+		for len in 0..msg.len() {
+			assert_eq!(value_len(&msg[..len]).unwrap(), None);
+		}
+		let mut buf = msg.clone();
+		assert_eq!(value_len(&buf).unwrap(), Some(msg.len()));
+		buf.extend_from_slice(&msg);
+		assert_eq!(value_len(&buf).unwrap(), Some(msg.len()));
+		assert!(value_len(&[0xc1]).is_err());
+	}
+
+	#[test]
+	fn test_next_message_stream() {
+		let mut buf = rmp_serde::to_vec_named(&StreamFileResponse::new(2, 5, 0, 5)).unwrap();
+		let len = buf.len();
+		buf.extend_from_slice(b"he");
+		assert!(next_message(&mut buf).unwrap().is_none());
+		buf.extend_from_slice(b"llo");
+		let (header, response) = next_message(&mut buf).unwrap().unwrap();
+		assert_eq!(header.to, 2);
+		assert_eq!(response.raw.len(), len);
+		assert_eq!(response.stream, b"hello");
+		assert!(buf.is_empty());
+	}
+
+	#[test]
+	fn test_next_message() {
+		let mut buf = rmp_serde::to_vec_named(&PongResponse::new(3)).unwrap();
+		let full = buf.clone();
+		buf.truncate(full.len() - 2);
+		assert!(next_message(&mut buf).unwrap().is_none());
+		buf = full.clone();
+		buf.extend_from_slice(&full);
+		let (header, response) = next_message(&mut buf).unwrap().unwrap();
+		assert_eq!(header.to, 3);
+		let pong: PongResponse = response.decode().unwrap();
+		assert_eq!(pong.body, "Pong!");
+		assert_eq!(buf, full);
+	}
+
+	#[test]
+	fn test_connection_limit() {
+		let limit = ConnectionLimit::new(2);
+		let first = limit.acquire();
+		let second = limit.acquire();
+		assert!(first.is_some() && second.is_some());
+		assert!(limit.acquire().is_none());
+		drop(first);
+		assert_eq!(limit.open(), 1);
+		assert!(limit.acquire().is_some());
+	}
+}
@@ -0,0 +1,212 @@
+use crate::error::Error;
+use crate::util::{atomic_write, private_write};
+use log::*;
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslConnector, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::{X509NameBuilder, X509};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+// The only crypt we support, TLS with a self-signed RSA certificate
+pub const CRYPT_TLS_RSA: &str = "tls-rsa";
+// First byte of a TLS ClientHello, used to detect implicit TLS on incoming connections
+pub const TLS_HANDSHAKE_BYTE: u8 = 0x16;
+
+const CERT_FILE: &str = "cert-rsa.pem";
+const KEY_FILE: &str = "key-rsa.pem";
+const CERT_DAYS: u32 = 3650;
+
+// Size of the largest TLS record
+const TLS_RECORD_SIZE: usize = 16 * 1024;
+
+// Socket of a peer connection, wrapped in TLS once negotiated
+pub enum Stream {
+	Plain(TcpStream),
+	Tls(SslStream<Socket>),
+}
+
+// Socket under a TLS stream, which reads from the socket itself until reads
+// are buffered, from then on it only reads what was handed to it by receive
+// so reading from the socket never has to hold up writing to it
+#[derive(Debug)]
+pub struct Socket {
+	tcp: TcpStream,
+	received: Option<VecDeque<u8>>,
+}
+
+impl Socket {
+	fn new(tcp: TcpStream) -> Socket {
+		Socket {
+			tcp,
+			received: None,
+		}
+	}
+}
+
+impl Read for Socket {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		let received = match &mut self.received {
+			Some(received) => received,
+			None => return self.tcp.read(buf),
+		};
+		if received.is_empty() {
+			return Err(ErrorKind::WouldBlock.into());
+		}
+		let len = std::cmp::min(buf.len(), received.len());
+		for (byte, received) in buf.iter_mut().zip(received.drain(..len)) {
+			*byte = received;
+		}
+		Ok(len)
+	}
+}
+
+impl Write for Socket {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.tcp.write(buf)
+	}
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.tcp.flush()
+	}
+}
+
+impl Stream {
+	pub fn tcp(&self) -> &TcpStream {
+		match self {
+			Stream::Plain(socket) => socket,
+			Stream::Tls(stream) => &stream.get_ref().tcp,
+		}
+	}
+	// Stop reading from the socket, bytes read from it elsewhere
+	// are handed to the stream by receive instead
+	pub fn buffer_reads(&mut self) {
+		if let Stream::Tls(stream) = self {
+			stream.get_mut().received = Some(VecDeque::new());
+		}
+	}
+	// Adds the bytes read from the socket to plain, decrypted if needed,
+	// only to be used once reads are buffered
+	pub fn receive(&mut self, bytes: &[u8], plain: &mut Vec<u8>) -> std::io::Result<()> {
+		let stream = match self {
+			Stream::Plain(_) => {
+				plain.extend_from_slice(bytes);
+				return Ok(());
+			}
+			Stream::Tls(stream) => stream,
+		};
+		if let Some(received) = &mut stream.get_mut().received {
+			received.extend(bytes);
+		}
+		// Decrypt all complete records, the rest waits for more bytes
+		let mut buf = [0u8; TLS_RECORD_SIZE];
+		loop {
+			match stream.read(&mut buf) {
+				Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+				Ok(len) => plain.extend_from_slice(&buf[..len]),
+				Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+				Err(err) => return Err(err),
+			}
+		}
+	}
+	pub fn crypt(&self) -> Option<String> {
+		match self {
+			Stream::Plain(_) => None,
+			Stream::Tls(_) => Some(String::from(CRYPT_TLS_RSA)),
+		}
+	}
+	// Wrap the socket in TLS as the connecting side, peer certificates
+	// are self-signed so they are not verified
+	pub fn connect_tls(&self) -> Result<Stream, Error> {
+		let mut builder = SslConnector::builder(SslMethod::tls())?;
+		builder.set_verify(SslVerifyMode::NONE);
+		let config = builder
+			.build()
+			.configure()?
+			.use_server_name_indication(false)
+			.verify_hostname(false);
+		let stream = config.connect("", Socket::new(self.tcp().try_clone()?))?;
+		Ok(Stream::Tls(stream))
+	}
+	// Wrap the socket in TLS as the accepting side
+	pub fn accept_tls(&self, acceptor: &SslAcceptor) -> Result<Stream, Error> {
+		let stream = acceptor.accept(Socket::new(self.tcp().try_clone()?))?;
+		Ok(Stream::Tls(stream))
+	}
+}
+
+impl Read for Stream {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		match self {
+			Stream::Plain(socket) => socket.read(buf),
+			Stream::Tls(stream) => stream.read(buf),
+		}
+	}
+}
+
+impl Write for Stream {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		match self {
+			Stream::Plain(socket) => socket.write(buf),
+			Stream::Tls(stream) => stream.write(buf),
+		}
+	}
+	fn flush(&mut self) -> std::io::Result<()> {
+		match self {
+			Stream::Plain(socket) => socket.flush(),
+			Stream::Tls(stream) => stream.flush(),
+		}
+	}
+}
+
+// Build the acceptor used for incoming connections from the certificate
+// in the data dir, generating a new self-signed one if there is none yet
+pub fn create_acceptor(data_path: &Path) -> Result<SslAcceptor, Error> {
+	let cert_path = data_path.join(CERT_FILE);
+	let key_path = data_path.join(KEY_FILE);
+	if !cert_path.is_file() || !key_path.is_file() {
+		info!("Generating new certificate in {:?}", cert_path);
+		let (cert, key) = generate_cert()?;
+		private_write(&key_path, &key)?;
+		atomic_write(&cert_path, &cert)?;
+	}
+	let cert = X509::from_pem(&std::fs::read(&cert_path)?)?;
+	let key = PKey::private_key_from_pem(&std::fs::read(&key_path)?)?;
+	let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+	builder.set_certificate(&cert)?;
+	builder.set_private_key(&key)?;
+	builder.check_private_key()?;
+	Ok(builder.build())
+}
+
+// Generates a self-signed certificate, returns it and its key PEM encoded
+fn generate_cert() -> Result<(Vec<u8>, Vec<u8>), Error> {
+	let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+
+	let mut name = X509NameBuilder::new()?;
+	name.append_entry_by_text("CN", "zerunet")?;
+	let name = name.build();
+
+	let mut serial = BigNum::new()?;
+	serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+	let serial = serial.to_asn1_integer()?;
+	let not_before = Asn1Time::days_from_now(0)?;
+	let not_after = Asn1Time::days_from_now(CERT_DAYS)?;
+
+	let mut cert = X509::builder()?;
+	cert.set_version(2)?;
+	cert.set_serial_number(&serial)?;
+	cert.set_subject_name(&name)?;
+	cert.set_issuer_name(&name)?;
+	cert.set_pubkey(&key)?;
+	cert.set_not_before(&not_before)?;
+	cert.set_not_after(&not_after)?;
+	cert.sign(&key, MessageDigest::sha256())?;
+
+	Ok((cert.build().to_pem()?, key.private_key_to_pem_pkcs8()?))
+}
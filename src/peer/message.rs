@@ -0,0 +1,353 @@
+use super::pack::PackedPeers;
+use crate::site::diff::Diffs;
+use crate::util::is_default;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+
+// Maximum number of bytes sent in a single getFile response
+pub const FILE_BUFF: usize = 512 * 1024;
+// Revision we report in handshakes
+pub const REV: usize = 4300;
+// First revision of the reference client that answers streamFile
+pub const STREAM_FILE_MIN_REV: usize = 1800;
+
+fn response_cmd() -> String {
+	String::from("response")
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Request<P = Params> {
+	pub cmd: String,
+	pub req_id: usize,
+	pub params: P,
+}
+
+// Params of all commands we understand, unused fields are left at their default
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Params {
+	#[serde(skip_serializing_if = "is_default")]
+	pub site: String,
+	#[serde(skip_serializing_if = "is_default")]
+	pub inner_path: String,
+	#[serde(skip_serializing_if = "is_default")]
+	pub location: usize,
+	#[serde(skip_serializing_if = "is_default")]
+	pub file_size: usize,
+	#[serde(skip_serializing_if = "is_default")]
+	pub read_bytes: usize,
+	#[serde(skip_serializing_if = "is_default")]
+	pub peers: Vec<ByteBuf>,
+	#[serde(skip_serializing_if = "is_default")]
+	pub peers_ipv6: Vec<ByteBuf>,
+	#[serde(skip_serializing_if = "is_default")]
+	pub peers_onion: Vec<ByteBuf>,
+	#[serde(skip_serializing_if = "is_default")]
+	pub need: usize,
+	#[serde(skip_serializing_if = "is_default")]
+	pub hashfield_raw: ByteBuf,
+	#[serde(skip_serializing_if = "is_default")]
+	pub hash_ids: Vec<u16>,
+	// Timestamp for listModified
+	#[serde(skip_serializing_if = "is_default")]
+	pub since: f64,
+	// Sent along with a handshake
+	#[serde(skip_serializing_if = "is_default")]
+	pub fileserver_port: usize,
+	#[serde(skip_serializing_if = "is_default")]
+	pub crypt_supported: Vec<String>,
+	// Content of the content.json pushed with an update
+	#[serde(skip_serializing_if = "is_default")]
+	pub body: ByteBuf,
+	#[serde(skip_serializing_if = "is_default")]
+	pub modified: f64,
+	// Changes of the files of the updated content.json, by path relative to it
+	#[serde(skip_serializing_if = "is_default")]
+	pub diffs: Diffs,
+}
+
+impl Params {
+	pub fn pex(site: String, packed: PackedPeers, need: usize) -> Params {
+		Params {
+			site,
+			peers: packed.ipv4,
+			peers_ipv6: packed.ipv6,
+			peers_onion: packed.onion,
+			need,
+			..Params::default()
+		}
+	}
+	pub fn site(site: String) -> Params {
+		Params {
+			site,
+			..Params::default()
+		}
+	}
+	pub fn packed_peers(&self) -> PackedPeers {
+		PackedPeers {
+			ipv4: self.peers.clone(),
+			ipv6: self.peers_ipv6.clone(),
+			onion: self.peers_onion.clone(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ErrorResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub error: String,
+}
+
+impl ErrorResponse {
+	pub fn new(to: usize, error: &str) -> ErrorResponse {
+		ErrorResponse {
+			cmd: response_cmd(),
+			to,
+			error: String::from(error),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HandshakeResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub crypt: Option<String>,
+	pub crypt_supported: Vec<String>,
+	pub fileserver_port: usize,
+	pub protocol: String,
+	pub port_opened: bool,
+	pub peer_id: String,
+	pub rev: usize,
+	pub target_ip: String,
+	pub version: String,
+}
+
+impl HandshakeResponse {
+	pub fn new(to: usize) -> HandshakeResponse {
+		HandshakeResponse {
+			cmd: response_cmd(),
+			to,
+			protocol: String::from("v2"),
+			rev: REV,
+			version: String::from("0.7.1"),
+			..HandshakeResponse::default()
+		}
+	}
+	pub fn supports_stream_file(&self) -> bool {
+		self.rev >= STREAM_FILE_MIN_REV
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PongResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub body: String,
+}
+
+impl PongResponse {
+	pub fn new(to: usize) -> PongResponse {
+		PongResponse {
+			cmd: response_cmd(),
+			to,
+			body: String::from("Pong!"),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct GetFileResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub body: ByteBuf,
+	// Position in the file after this chunk
+	pub location: usize,
+	// Total size of the file
+	pub size: usize,
+}
+
+impl GetFileResponse {
+	pub fn new(to: usize, body: Vec<u8>, location: usize, size: usize) -> GetFileResponse {
+		GetFileResponse {
+			cmd: response_cmd(),
+			to,
+			body: ByteBuf::from(body),
+			location,
+			size,
+		}
+	}
+}
+
+// Header of a streamFile response, followed by `stream_bytes` raw bytes
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct StreamFileResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub stream_bytes: usize,
+	pub location: usize,
+	pub size: usize,
+}
+
+impl StreamFileResponse {
+	pub fn new(to: usize, stream_bytes: usize, location: usize, size: usize) -> StreamFileResponse {
+		StreamFileResponse {
+			cmd: response_cmd(),
+			to,
+			stream_bytes,
+			location,
+			size,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PexResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub peers: Vec<ByteBuf>,
+	pub peers_ipv6: Vec<ByteBuf>,
+	pub peers_onion: Vec<ByteBuf>,
+}
+
+impl PexResponse {
+	pub fn new(to: usize, packed: PackedPeers) -> PexResponse {
+		PexResponse {
+			cmd: response_cmd(),
+			to,
+			peers: packed.ipv4,
+			peers_ipv6: packed.ipv6,
+			peers_onion: packed.onion,
+		}
+	}
+	pub fn packed_peers(self) -> PackedPeers {
+		PackedPeers {
+			ipv4: self.peers,
+			ipv6: self.peers_ipv6,
+			onion: self.peers_onion,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct OkResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub ok: String,
+}
+
+impl OkResponse {
+	pub fn new(to: usize, ok: &str) -> OkResponse {
+		OkResponse {
+			cmd: response_cmd(),
+			to,
+			ok: String::from(ok),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HashfieldResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub hashfield_raw: ByteBuf,
+}
+
+impl HashfieldResponse {
+	pub fn new(to: usize, hashfield_raw: Vec<u8>) -> HashfieldResponse {
+		HashfieldResponse {
+			cmd: response_cmd(),
+			to,
+			hashfield_raw: ByteBuf::from(hashfield_raw),
+		}
+	}
+}
+
+// Modification times of content.json files by inner path
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ListModifiedResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub modified_files: BTreeMap<String, f64>,
+}
+
+impl ListModifiedResponse {
+	pub fn new(to: usize, modified_files: BTreeMap<String, f64>) -> ListModifiedResponse {
+		ListModifiedResponse {
+			cmd: response_cmd(),
+			to,
+			modified_files,
+		}
+	}
+}
+
+// Packed addresses of peers having the optional file with the hash id
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct FindHashIdsResponse {
+	pub cmd: String,
+	pub to: usize,
+	pub peers: BTreeMap<u16, Vec<ByteBuf>>,
+	pub peers_ipv6: BTreeMap<u16, Vec<ByteBuf>>,
+	pub peers_onion: BTreeMap<u16, Vec<ByteBuf>>,
+}
+
+impl FindHashIdsResponse {
+	pub fn new(to: usize, found: BTreeMap<u16, Vec<String>>) -> FindHashIdsResponse {
+		let mut response = FindHashIdsResponse {
+			cmd: response_cmd(),
+			to,
+			..FindHashIdsResponse::default()
+		};
+		for (hash_id, addresses) in found {
+			let packed = PackedPeers::pack(&addresses);
+			response.peers.insert(hash_id, packed.ipv4);
+			response.peers_ipv6.insert(hash_id, packed.ipv6);
+			response.peers_onion.insert(hash_id, packed.onion);
+		}
+		response
+	}
+	pub fn unpack(self) -> BTreeMap<u16, Vec<String>> {
+		let mut found: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+		for (hash_id, ipv4) in self.peers {
+			found.entry(hash_id).or_default().extend(
+				PackedPeers {
+					ipv4,
+					..PackedPeers::default()
+				}
+				.unpack(),
+			);
+		}
+		for (hash_id, ipv6) in self.peers_ipv6 {
+			found.entry(hash_id).or_default().extend(
+				PackedPeers {
+					ipv6,
+					..PackedPeers::default()
+				}
+				.unpack(),
+			);
+		}
+		for (hash_id, onion) in self.peers_onion {
+			found.entry(hash_id).or_default().extend(
+				PackedPeers {
+					onion,
+					..PackedPeers::default()
+				}
+				.unpack(),
+			);
+		}
+		found
+	}
+}
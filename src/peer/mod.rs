@@ -1,29 +1,58 @@
 pub mod connections;
+pub mod crypt;
+pub mod message;
+pub mod pack;
 pub mod peer_manager;
+pub mod socks;
 
 use crate::error::Error;
+use crate::optional_files::hashfield::Hashfield;
 use crate::site::address::Address as SiteAddress;
+use crate::site::diff::Diffs;
 use crate::tracker::zero_announcer::Announce;
-use crate::util::is_default;
+use crate::util::{is_default, HashWriter};
 use actix::{prelude::*, Actor};
 use chrono::{DateTime, Duration, Utc};
-use futures::executor::block_on;
+use connections::{ConnectionLimit, PeerConnection, TcpConnection};
 use ipnetwork::IpNetwork;
 use log::*;
+use message::FILE_BUFF;
+use pack::PackedPeers;
+use peer_manager::{update_peers, PeerManager};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 use serde_json::json;
-use std::collections::HashMap;
-use std::io::Write;
-use zeronet_protocol::{templates, Address as PeerAddress, ZeroConnection};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use zeronet_protocol::{templates, Address as PeerAddress};
+
+// Requests time out unless the peer answers within this time
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+// Slowest transfer speed we wait for when downloading files, in bytes per second
+const MIN_TRANSFER_SPEED: usize = 10 * 1024;
+// Reputation gained for every successful request
+const REPUTATION_SUCCESS: isize = 1;
+// Reputation lost for every failed request
+const REPUTATION_ERROR: isize = 2;
+// Reputation lost for every file that did not match its hash
+const REPUTATION_BAD_FILE: isize = 20;
 
 pub struct Peer {
 	address: PeerAddress,
-	connection: Option<ZeroConnection>,
+	connection: Option<PeerConnection>,
+	// Set for peers spun up by the peer manager, peers found via pex are sent here
+	peer_manager: Option<Addr<PeerManager>>,
+	// Shared with all peers of the peer manager to cap the open connections
+	connection_limit: Option<ConnectionLimit>,
+	// Number of requests waiting for a response
+	pending_requests: usize,
 	reputation: isize,
 	time_found: DateTime<Utc>,
 	time_added: DateTime<Utc>,
 	time_response: DateTime<Utc>,
+	time_request: DateTime<Utc>,
 	last_content_json_update: DateTime<Utc>,
 	download_bytes: usize,
 	download_time: Duration,
@@ -36,10 +65,14 @@ impl Peer {
 		Peer {
 			address,
 			connection: None,
+			peer_manager: None,
+			connection_limit: None,
+			pending_requests: 0,
 			reputation: 0,
 			time_found: Utc::now(),
 			time_added: Utc::now(),
 			time_response: Utc::now(),
+			time_request: Utc::now(),
 			last_content_json_update: Utc::now(),
 			download_bytes: 0,
 			download_time: Duration::seconds(0),
@@ -47,18 +80,109 @@ impl Peer {
 			errors: 0,
 		}
 	}
-	pub fn connect(&mut self) -> Result<(), Error> {
-		if self.connection.is_none() {
-			let conn = ZeroConnection::from_address(self.address.clone());
-			if conn.is_err() {
-				trace!(
-					"Failed to establish connection to {}.",
-					self.address.to_string()
-				);
+	// Returns the connection to the peer, connecting first if there is none
+	pub fn connect(&mut self) -> Result<PeerConnection, Error> {
+		if let Some(conn) = &self.connection {
+			if !conn.is_closed() {
+				return Ok(conn.clone());
+			}
+		}
+		self.connection = None;
+		let slot = match &self.connection_limit {
+			Some(limit) => match limit.acquire() {
+				Some(slot) => Some(slot),
+				None => {
+					trace!(
+						"Connection limit reached, not connecting to {}",
+						self.address.to_string()
+					);
+					return Err(Error::ConnectionLimit);
+				}
+			},
+			None => None,
+		};
+		let conn = TcpConnection::connect(&self.address.to_string());
+		if conn.is_err() {
+			trace!(
+				"Failed to establish connection to {}.",
+				self.address.to_string()
+			);
+		}
+		let conn = PeerConnection::start(conn?, slot)?;
+		self.connection = Some(conn.clone());
+		Ok(conn)
+	}
+	// Closes the connection, requests still waiting keep it open until they're done
+	pub fn disconnect(&mut self) {
+		if self.connection.take().is_some() {
+			trace!("Disconnecting from {}", self.address.to_string());
+		}
+	}
+	pub fn is_connected(&self) -> bool {
+		match &self.connection {
+			Some(conn) => !conn.is_closed(),
+			None => false,
+		}
+	}
+	// Time since the last request finished, None while requests are pending
+	pub fn idle_time(&self) -> Option<Duration> {
+		if self.pending_requests > 0 {
+			return None;
+		}
+		Some(Utc::now() - self.time_request)
+	}
+	pub fn stats(&self) -> PeerStats {
+		PeerStats {
+			reputation: self.reputation,
+			download_bytes: self.download_bytes,
+			download_time: self.download_time,
+			bad_files: self.bad_files,
+			errors: self.errors,
+		}
+	}
+	// Marks the peer as busy until the result is recorded
+	fn start_request(&mut self) {
+		self.pending_requests += 1;
+		self.time_request = Utc::now();
+	}
+	// Keep track of how fast the peer serves files
+	fn record_download(&mut self, bytes: usize, start: DateTime<Utc>) {
+		self.download_bytes += bytes;
+		self.download_time = self.download_time + (Utc::now() - start);
+	}
+	// Keep track of how the peer responds
+	fn record_result<T>(&mut self, result: &Result<T, Error>) {
+		self.pending_requests = self.pending_requests.saturating_sub(1);
+		self.time_request = Utc::now();
+		match result {
+			Ok(_) => {
+				self.time_response = Utc::now();
+				self.reputation += REPUTATION_SUCCESS;
+			}
+			// Our own limit, not the peer's fault
+			Err(Error::ConnectionLimit) => {}
+			Err(_) => {
+				self.errors += 1;
+				self.reputation -= REPUTATION_ERROR;
 			}
-			self.connection = Some(conn?);
 		}
-		Ok(())
+	}
+	// Send a request to the peer, connecting first if needed
+	fn request<P: Serialize + 'static, R: DeserializeOwned + 'static>(
+		&mut self,
+		cmd: &str,
+		params: P,
+	) -> impl ActorFuture<Output = Result<R, Error>, Actor = Self> {
+		self.start_request();
+		let response = self
+			.connect()
+			.map(|conn| conn.request(cmd, params, REQUEST_TIMEOUT));
+		async move { response?.await?.decode::<R>() }
+			.into_actor(self)
+			.map(|result, act, _ctx| {
+				act.record_result(&result);
+				result
+			})
 	}
 	// pub fn request(
 	// 	&mut self,
@@ -106,8 +230,6 @@ impl Peer {
 
 	// 	Ok(())
 	// }
-	// fn pex() {}
-	// fn list_modified() {}
 	// fn update_hashfield() {}
 	// fn find_hash_ids() {}
 	// fn send_my_hashfield() {}
@@ -121,6 +243,141 @@ impl Actor for Peer {
 	type Context = Context<Self>;
 }
 
+// Time to wait for a transfer of the given size
+fn transfer_timeout(bytes: usize) -> std::time::Duration {
+	REQUEST_TIMEOUT + std::time::Duration::from_secs((bytes / MIN_TRANSFER_SPEED) as u64)
+}
+
+// Download a whole file to the sink, in chunks if needed
+async fn get_file<W: Write>(
+	conn: PeerConnection,
+	peer: String,
+	msg: FileGetRequest,
+	mut sink: W,
+) -> Result<W, Error> {
+	trace!(
+		"Requesting 'zero://{}/{}' from {}",
+		msg.site_address,
+		msg.inner_path,
+		peer
+	);
+	let stream = conn.supports_stream_file();
+	let mut received = 0;
+	loop {
+		// Peers may return less than requested, so always continue
+		// from what we've actually received so far
+		let location = received;
+		let mut params = json!({
+			"site": msg.site_address.to_string(),
+			"inner_path": msg.inner_path,
+			"location": location,
+			"file_size": msg.file_size,
+		});
+		let size = if stream {
+			// The body is not msgpacked so it is passed along as is, asking for
+			// at most FILE_BUFF at a time keeps what's buffered per request bounded
+			let read_bytes = match msg.file_size {
+				s if s > location => std::cmp::min(s - location, FILE_BUFF),
+				_ => FILE_BUFF,
+			};
+			params["read_bytes"] = json!(read_bytes);
+			let response = conn
+				.request("streamFile", params, transfer_timeout(read_bytes))
+				.await?;
+			let header: message::StreamFileResponse = response.decode()?;
+			if location + header.stream_bytes > header.size {
+				error!(
+					"{} wants to stream more bytes than the size of zero://{}/{}",
+					peer, msg.site_address, msg.inner_path
+				);
+				return Err(Error::MissingError);
+			}
+			sink.write_all(&response.stream)?;
+			received += response.stream.len();
+			header.size
+		} else {
+			let response = conn
+				.request("getFile", params, transfer_timeout(FILE_BUFF))
+				.await?;
+			let response: message::GetFileResponse = response.decode()?;
+			sink.write_all(&response.body)?;
+			received += response.body.len();
+			response.size
+		};
+		if received > size {
+			error!(
+				"{} returned more bytes than the size of zero://{}/{}",
+				peer, msg.site_address, msg.inner_path
+			);
+			return Err(Error::MissingError);
+		}
+		if received == location && location < size {
+			error!(
+				"{} returned an empty chunk for zero://{}/{} at {}",
+				peer, msg.site_address, msg.inner_path, location
+			);
+			return Err(Error::MissingError);
+		}
+		if let Some(progress) = &msg.progress {
+			let _ = progress.do_send(FileProgress {
+				inner_path: msg.inner_path.clone(),
+				downloaded: received,
+				size,
+			});
+		}
+		if received >= size {
+			break;
+		}
+	}
+	if msg.file_size != 0 && received != msg.file_size {
+		error!(
+			"Size of zero://{}/{} from {} is {}, expected {}",
+			msg.site_address, msg.inner_path, peer, received, msg.file_size
+		);
+		return Err(Error::MissingError);
+	}
+
+	Ok(sink)
+}
+
+// Snapshot of how a peer has been performing, used to rank peers
+#[derive(Debug, Clone)]
+pub struct PeerStats {
+	pub reputation: isize,
+	pub download_bytes: usize,
+	pub download_time: Duration,
+	pub bad_files: usize,
+	pub errors: usize,
+}
+
+impl Default for PeerStats {
+	fn default() -> PeerStats {
+		PeerStats {
+			reputation: 0,
+			download_bytes: 0,
+			download_time: Duration::seconds(0),
+			bad_files: 0,
+			errors: 0,
+		}
+	}
+}
+
+impl PeerStats {
+	// Average download speed in bytes per second
+	pub fn speed(&self) -> f64 {
+		let millis = self.download_time.num_milliseconds();
+		if millis <= 0 {
+			return 0.0;
+		}
+		self.download_bytes as f64 * 1000.0 / millis as f64
+	}
+	// Higher is better, speed only counts logarithmically so a peer
+	// serving bad files loses more than a fast connection makes up for
+	pub fn score(&self) -> f64 {
+		self.reputation as f64 + (self.speed() / 1024.0).ln_1p()
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum PeerCommand {
 	StreamFile,
@@ -149,52 +406,385 @@ pub struct PeerMessage {
 pub struct FileGetRequest {
 	pub inner_path: String,
 	pub site_address: SiteAddress,
+	// Expected size of the file, 0 if unknown
+	pub file_size: usize,
+	// Receives a FileProgress message after every downloaded chunk
+	pub progress: Option<Recipient<FileProgress>>,
 }
 
 impl Message for FileGetRequest {
 	type Result = Result<ByteBuf, Error>;
 }
 
+pub struct FileProgress {
+	pub inner_path: String,
+	pub downloaded: usize,
+	pub size: usize,
+}
+
+impl Message for FileProgress {
+	type Result = ();
+}
+
 impl Handler<FileGetRequest> for Peer {
-	type Result = Result<ByteBuf, Error>;
+	type Result = ResponseActFuture<Self, Result<ByteBuf, Error>>;
 
 	fn handle(&mut self, msg: FileGetRequest, _ctx: &mut Context<Self>) -> Self::Result {
-		self.connect()?;
-		let mut conn = match &mut self.connection {
-			Some(conn) => conn,
-			None => return Err(Error::MissingError),
+		let start = Utc::now();
+		self.start_request();
+		let connection = self.connect();
+		let address = self.address.to_string();
+		let buf = Vec::with_capacity(msg.file_size);
+		let download = async move { get_file(connection?, address, msg, buf).await };
+		Box::pin(download.into_actor(self).map(move |result, act, _ctx| {
+			if let Ok(buf) = &result {
+				act.record_download(buf.len(), start);
+			}
+			act.record_result(&result);
+			result.map(ByteBuf::from)
+		}))
+	}
+}
+
+/// Message struct used to download a file from a peer straight to disk,
+/// returns the size and sha512t hash of what was written
+pub struct FileDownload {
+	pub inner_path: String,
+	pub site_address: SiteAddress,
+	// Expected size of the file, 0 if unknown
+	pub file_size: usize,
+	// File the download is written to, removed again if the download fails
+	pub path: PathBuf,
+}
+
+impl Message for FileDownload {
+	type Result = Result<DownloadedFile, Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadedFile {
+	pub size: usize,
+	pub sha512: String,
+}
+
+impl Handler<FileDownload> for Peer {
+	type Result = ResponseActFuture<Self, Result<DownloadedFile, Error>>;
+
+	fn handle(&mut self, msg: FileDownload, _ctx: &mut Context<Self>) -> Self::Result {
+		let start = Utc::now();
+		self.start_request();
+		let connection = self.connect();
+		let address = self.address.to_string();
+		let path = msg.path;
+		let req = FileGetRequest {
+			inner_path: msg.inner_path,
+			site_address: msg.site_address,
+			file_size: msg.file_size,
+			progress: None,
 		};
-		trace!(
-			"Requesting 'zero://{}/{}' from {}",
-			msg.site_address,
-			msg.inner_path,
-			self.address.to_string()
+		let download = async move {
+			let file = std::fs::File::create(&path)?;
+			let sink = HashWriter::new(BufWriter::new(file));
+			let result = match connection {
+				Ok(conn) => get_file(conn, address, req, sink).await,
+				Err(err) => Err(err),
+			};
+			let downloaded = result.and_then(|sink| {
+				let size = sink.written();
+				let (mut file, sha512) = sink.finish();
+				file.flush()?;
+				Ok(DownloadedFile { size, sha512 })
+			});
+			if downloaded.is_err() {
+				let _ = std::fs::remove_file(&path);
+			}
+			downloaded
+		};
+		Box::pin(download.into_actor(self).map(move |result, act, _ctx| {
+			if let Ok(downloaded) = &result {
+				act.record_download(downloaded.size, start);
+			}
+			act.record_result(&result);
+			result
+		}))
+	}
+}
+
+/// Message struct used to exchange peers of a site with a peer,
+/// returns the number of peers received
+pub struct Pex {
+	pub site_address: SiteAddress,
+	// Addresses of the site's peers we share
+	pub peers: Vec<String>,
+	pub need: usize,
+}
+
+impl Message for Pex {
+	type Result = Result<usize, Error>;
+}
+
+impl Handler<Pex> for Peer {
+	type Result = ResponseActFuture<Self, Result<usize, Error>>;
+
+	fn handle(&mut self, msg: Pex, _ctx: &mut Context<Self>) -> Self::Result {
+		let params = message::Params::pex(
+			msg.site_address.to_string(),
+			PackedPeers::pack(&msg.peers),
+			msg.need,
 		);
-		let request = zeronet_protocol::templates::GetFile {
-			site: msg.site_address.to_string(),
+		let request = self.request("pex", params);
+		Box::pin(request.map(move |result, act, _ctx| {
+			let response: message::PexResponse = result?;
+			let addresses = response.packed_peers().unpack();
+			trace!(
+				"Received {} peers for {} from {}",
+				addresses.len(),
+				msg.site_address.get_address_short(),
+				act.address.to_string()
+			);
+			if let Some(peer_manager) = &act.peer_manager {
+				update_peers(peer_manager, &addresses, &msg.site_address);
+			}
+			Ok(addresses.len())
+		}))
+	}
+}
+
+/// Message struct used to ask a peer for the content.json files of a site
+/// modified since a timestamp, returns their modification times by inner path
+pub struct ListModified {
+	pub site_address: SiteAddress,
+	pub since: f64,
+}
+
+impl Message for ListModified {
+	type Result = Result<BTreeMap<String, f64>, Error>;
+}
+
+impl Handler<ListModified> for Peer {
+	type Result = ResponseActFuture<Self, Result<BTreeMap<String, f64>, Error>>;
+
+	fn handle(&mut self, msg: ListModified, _ctx: &mut Context<Self>) -> Self::Result {
+		let params = message::Params {
+			since: msg.since,
+			..message::Params::site(msg.site_address.to_string())
+		};
+		let request = self.request("listModified", params);
+		Box::pin(request.map(|result, _act, _ctx| {
+			let response: message::ListModifiedResponse = result?;
+			Ok(response.modified_files)
+		}))
+	}
+}
+
+/// Message struct used to request the hashfield of a site from a peer
+pub struct GetHashfield {
+	pub site_address: SiteAddress,
+}
+
+impl Message for GetHashfield {
+	type Result = Result<Hashfield, Error>;
+}
+
+impl Handler<GetHashfield> for Peer {
+	type Result = ResponseActFuture<Self, Result<Hashfield, Error>>;
+
+	fn handle(&mut self, msg: GetHashfield, _ctx: &mut Context<Self>) -> Self::Result {
+		let params = message::Params::site(msg.site_address.to_string());
+		let request = self.request("getHashfield", params);
+		Box::pin(request.map(|result, _act, _ctx| {
+			let response: message::HashfieldResponse = result?;
+			Ok(Hashfield::from_bytes(&response.hashfield_raw))
+		}))
+	}
+}
+
+/// Message struct used to send our hashfield of a site to a peer
+pub struct SetHashfield {
+	pub site_address: SiteAddress,
+	pub hashfield: Hashfield,
+}
+
+impl Message for SetHashfield {
+	type Result = Result<(), Error>;
+}
+
+impl Handler<SetHashfield> for Peer {
+	type Result = ResponseActFuture<Self, Result<(), Error>>;
+
+	fn handle(&mut self, msg: SetHashfield, _ctx: &mut Context<Self>) -> Self::Result {
+		let params = message::Params {
+			hashfield_raw: ByteBuf::from(msg.hashfield.to_bytes()),
+			..message::Params::site(msg.site_address.to_string())
+		};
+		let request = self.request("setHashfield", params);
+		Box::pin(request.map(|result, _act, _ctx| {
+			let _: message::OkResponse = result?;
+			Ok(())
+		}))
+	}
+}
+
+/// Message struct used to push a new version of a content.json file to a peer
+pub struct Update {
+	pub site_address: SiteAddress,
+	pub inner_path: String,
+	pub body: ByteBuf,
+	pub modified: f64,
+	// Changes of the files listed in the content.json
+	pub diffs: Diffs,
+}
+
+impl Message for Update {
+	type Result = Result<(), Error>;
+}
+
+impl Handler<Update> for Peer {
+	type Result = ResponseActFuture<Self, Result<(), Error>>;
+
+	fn handle(&mut self, msg: Update, _ctx: &mut Context<Self>) -> Self::Result {
+		let params = message::Params {
 			inner_path: msg.inner_path,
-			location: 0,
-			file_size: 0,
+			body: msg.body,
+			modified: msg.modified,
+			diffs: msg.diffs,
+			..message::Params::site(msg.site_address.to_string())
+		};
+		let request = self.request("update", params);
+		Box::pin(request.map(|result, _act, _ctx| {
+			let _: message::OkResponse = result?;
+			Ok(())
+		}))
+	}
+}
+
+/// Message struct used to ask a peer which peers have optional files
+/// with the given hash ids, returns their addresses by hash id
+pub struct FindHashIds {
+	pub site_address: SiteAddress,
+	pub hash_ids: Vec<u16>,
+}
+
+impl Message for FindHashIds {
+	type Result = Result<BTreeMap<u16, Vec<String>>, Error>;
+}
+
+impl Handler<FindHashIds> for Peer {
+	type Result = ResponseActFuture<Self, Result<BTreeMap<u16, Vec<String>>, Error>>;
+
+	fn handle(&mut self, msg: FindHashIds, _ctx: &mut Context<Self>) -> Self::Result {
+		let params = message::Params {
+			hash_ids: msg.hash_ids,
+			..message::Params::site(msg.site_address.to_string())
 		};
-		let res = block_on(conn.request("getFile", json!(request)))?;
-		let response: templates::GetFileResponse = res.body()?;
+		let site_address = msg.site_address;
+		let request = self.request("findHashIds", params);
+		Box::pin(request.map(move |result, act, _ctx| {
+			let response: message::FindHashIdsResponse = result?;
+			let found = response.unpack();
+			if let Some(peer_manager) = &act.peer_manager {
+				let addresses: Vec<String> = found.values().flatten().cloned().collect();
+				update_peers(peer_manager, &addresses, &site_address);
+			}
+			Ok(found)
+		}))
+	}
+}
+
+/// Message struct used to request the stats of a peer
+pub struct PeerStatsRequest {}
+
+impl Message for PeerStatsRequest {
+	type Result = Result<PeerStats, Error>;
+}
+
+impl Handler<PeerStatsRequest> for Peer {
+	type Result = Result<PeerStats, Error>;
 
-		return Ok(response.body);
+	fn handle(&mut self, _msg: PeerStatsRequest, _ctx: &mut Context<Self>) -> Self::Result {
+		Ok(self.stats())
+	}
+}
+
+// Connection state of a peer, used by the peer manager to close connections
+#[derive(Debug, Clone)]
+pub struct PeerStatus {
+	pub connected: bool,
+	// Time since the last request, None while requests are pending
+	pub idle_time: Option<Duration>,
+	pub stats: PeerStats,
+}
+
+/// Message struct used to request the connection state of a peer
+pub struct PeerStatusRequest {}
+
+impl Message for PeerStatusRequest {
+	type Result = Result<PeerStatus, Error>;
+}
+
+impl Handler<PeerStatusRequest> for Peer {
+	type Result = Result<PeerStatus, Error>;
+
+	fn handle(&mut self, _msg: PeerStatusRequest, _ctx: &mut Context<Self>) -> Self::Result {
+		Ok(PeerStatus {
+			connected: self.is_connected(),
+			idle_time: self.idle_time(),
+			stats: self.stats(),
+		})
+	}
+}
+
+/// Message struct used to close the connection to a peer,
+/// stopping the peer's actor as well if `stop` is set
+pub struct Disconnect {
+	pub stop: bool,
+}
+
+impl Message for Disconnect {
+	type Result = ();
+}
+
+impl Handler<Disconnect> for Peer {
+	type Result = ();
+
+	fn handle(&mut self, msg: Disconnect, ctx: &mut Context<Self>) -> Self::Result {
+		self.disconnect();
+		if msg.stop {
+			ctx.stop();
+		}
+	}
+}
+
+/// Message struct used to report that a file received from
+/// a peer did not match its hash
+pub struct BadFile {
+	pub site_address: SiteAddress,
+	pub inner_path: String,
+}
+
+impl Message for BadFile {
+	type Result = ();
+}
+
+impl Handler<BadFile> for Peer {
+	type Result = ();
+
+	fn handle(&mut self, msg: BadFile, _ctx: &mut Context<Self>) -> Self::Result {
+		warn!(
+			"{} served a bad version of zero://{}/{}",
+			self.address.to_string(),
+			msg.site_address,
+			msg.inner_path
+		);
+		self.bad_files += 1;
+		self.reputation -= REPUTATION_BAD_FILE;
 	}
 }
 
 impl Handler<Announce> for Peer {
-	type Result = Result<templates::AnnounceResponse, Error>;
+	type Result = ResponseActFuture<Self, Result<templates::AnnounceResponse, Error>>;
 
 	fn handle(&mut self, msg: Announce, _ctx: &mut Context<Self>) -> Self::Result {
-		self.connect()?;
-		let mut conn = match &mut self.connection {
-			Some(conn) => conn,
-			None => return Err(Error::MissingError),
-		};
-		let res = block_on(conn.request("announce", msg.req))?;
-		let response: templates::AnnounceResponse = res.body()?;
-
-		Ok(response)
+		Box::pin(self.request("announce", msg.req))
 	}
 }
@@ -0,0 +1,185 @@
+use serde_bytes::ByteBuf;
+use std::net::{IpAddr, SocketAddr};
+
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+// Peer addresses packed the way they are exchanged over pex,
+// split up by address type
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PackedPeers {
+	pub ipv4: Vec<ByteBuf>,
+	pub ipv6: Vec<ByteBuf>,
+	pub onion: Vec<ByteBuf>,
+}
+
+impl PackedPeers {
+	// Packs "ip:port", "[ipv6]:port" and "name.onion:port" addresses,
+	// addresses that can't be packed are skipped
+	pub fn pack(addresses: &[String]) -> PackedPeers {
+		let mut packed = PackedPeers::default();
+		for address in addresses {
+			if let Ok(socket_addr) = address.parse::<SocketAddr>() {
+				let bytes = pack_ip_port(&socket_addr);
+				match socket_addr.ip() {
+					IpAddr::V4(_) => packed.ipv4.push(ByteBuf::from(bytes)),
+					IpAddr::V6(_) => packed.ipv6.push(ByteBuf::from(bytes)),
+				}
+			} else if let Some(bytes) = pack_onion_port(address) {
+				packed.onion.push(ByteBuf::from(bytes));
+			}
+		}
+		packed
+	}
+	pub fn unpack(&self) -> Vec<String> {
+		self
+			.ipv4
+			.iter()
+			.chain(self.ipv6.iter())
+			.filter_map(|bytes| unpack_ip_port(bytes))
+			.chain(
+				self
+					.onion
+					.iter()
+					.filter_map(|bytes| unpack_onion_port(bytes)),
+			)
+			.collect()
+	}
+	pub fn len(&self) -> usize {
+		self.ipv4.len() + self.ipv6.len() + self.onion.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+// Ports are packed in native byte order by the reference client,
+// which is little endian on all platforms it supports
+fn pack_port(port: u16) -> [u8; 2] {
+	port.to_le_bytes()
+}
+
+fn unpack_port(bytes: &[u8]) -> u16 {
+	u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn pack_ip_port(address: &SocketAddr) -> Vec<u8> {
+	let mut bytes = match address.ip() {
+		IpAddr::V4(ip) => ip.octets().to_vec(),
+		IpAddr::V6(ip) => ip.octets().to_vec(),
+	};
+	bytes.extend_from_slice(&pack_port(address.port()));
+	bytes
+}
+
+fn unpack_ip_port(bytes: &[u8]) -> Option<String> {
+	let (ip, port) = bytes.split_at(bytes.len().checked_sub(2)?);
+	let ip = match ip.len() {
+		4 => {
+			let mut octets = [0u8; 4];
+			octets.copy_from_slice(ip);
+			IpAddr::from(octets)
+		}
+		16 => {
+			let mut octets = [0u8; 16];
+			octets.copy_from_slice(ip);
+			IpAddr::from(octets)
+		}
+		_ => return None,
+	};
+	Some(SocketAddr::new(ip, unpack_port(port)).to_string())
+}
+
+fn pack_onion_port(address: &str) -> Option<Vec<u8>> {
+	let mut parts = address.rsplitn(2, ':');
+	let port: u16 = parts.next()?.parse().ok()?;
+	let host = parts.next()?;
+	if !host.ends_with(".onion") {
+		return None;
+	}
+	let mut bytes = base32_decode(host.trim_end_matches(".onion"))?;
+	bytes.extend_from_slice(&pack_port(port));
+	Some(bytes)
+}
+
+fn unpack_onion_port(bytes: &[u8]) -> Option<String> {
+	let (onion, port) = bytes.split_at(bytes.len().checked_sub(2)?);
+	if onion.is_empty() {
+		return None;
+	}
+	Some(format!(
+		"{}.onion:{}",
+		base32_encode(onion),
+		unpack_port(port)
+	))
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+	let mut result = String::new();
+	let mut buffer: u32 = 0;
+	let mut bits = 0;
+	for byte in bytes {
+		buffer = (buffer << 8) | *byte as u32;
+		bits += 8;
+		while bits >= 5 {
+			bits -= 5;
+			result.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+		}
+	}
+	if bits > 0 {
+		result.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+	}
+	result
+}
+
+fn base32_decode(string: &str) -> Option<Vec<u8>> {
+	let mut result = Vec::new();
+	let mut buffer: u32 = 0;
+	let mut bits = 0;
+	for c in string.to_ascii_lowercase().bytes() {
+		let value = BASE32_ALPHABET.iter().position(|a| *a == c)? as u32;
+		buffer = (buffer << 5) | value;
+		bits += 5;
+		if bits >= 8 {
+			bits -= 8;
+			result.push((buffer >> bits) as u8);
+		}
+	}
+	Some(result)
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_ipv4_packing() {
+		let packed = PackedPeers::pack(&[String::from("1.2.3.4:15441")]);
+		assert_eq!(packed.ipv4.len(), 1);
+		assert_eq!(packed.ipv4[0].to_vec(), vec![1, 2, 3, 4, 0x51, 0x3c]);
+		assert_eq!(packed.unpack(), vec![String::from("1.2.3.4:15441")]);
+	}
+
+	#[test]
+	fn test_ipv6_packing() {
+		let packed = PackedPeers::pack(&[String::from("[2001:db8::1]:443")]);
+		assert_eq!(packed.ipv6.len(), 1);
+		assert_eq!(packed.ipv6[0].len(), 18);
+		assert_eq!(packed.unpack(), vec![String::from("[2001:db8::1]:443")]);
+	}
+
+	#[test]
+	fn test_onion_packing() {
+		let addresses = vec![String::from("boot3rdez4rzn36x.onion:15441")];
+		let packed = PackedPeers::pack(&addresses);
+		assert_eq!(packed.onion.len(), 1);
+		assert_eq!(packed.onion[0].len(), 12);
+		assert_eq!(packed.unpack(), addresses);
+	}
+
+	#[test]
+	fn test_invalid_addresses() {
+		let packed = PackedPeers::pack(&[String::from("example.com:80"), String::from("1.2.3.4")]);
+		assert!(packed.is_empty());
+	}
+}
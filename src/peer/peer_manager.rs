@@ -1,21 +1,39 @@
-use super::Peer;
+use super::connections::ConnectionLimit;
+use super::{Disconnect, Peer, PeerStatus, PeerStatusRequest};
+use crate::environment::Environment;
 use crate::error::Error;
+use crate::site::address::Address as SiteAddress;
 use crate::site::site_manager::SiteManager;
 use actix::{prelude::*, Actor, Addr};
+use chrono::Duration;
+use futures::future::{join_all, FutureExt};
 use log::*;
 use std::collections::HashMap;
 use std::sync::mpsc::{channel, RecvError};
 use zeronet_protocol::Address as PeerAddress;
 
-// start_peer_manager starts the peer manager actor in a new system thread
+// How often idle connections are closed and bad peers are forgotten
+const REAP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+// Share of the global connection limit kept free when over the limit,
+// so new sites and better peers can still connect
+const CONNECTION_HEADROOM: f64 = 0.1;
+// Disconnected peers are forgotten once they've been idle this long
+// with a negative score
+const FORGET_AFTER: i64 = 60 * 60;
+
+// start_peer_manager starts the peer manager actor in a new system thread,
+// the connection limit is shared with the file server's incoming connections
 pub fn start_peer_manager(
+	env: &Environment,
 	site_manager_addr: Addr<SiteManager>,
+	connection_limit: ConnectionLimit,
 ) -> Result<Addr<PeerManager>, RecvError> {
 	info!("Starting peer manager");
 
+	let idle_timeout = Duration::seconds(env.connection_idle_timeout as i64);
 	let (sender, receiver) = channel();
 	std::thread::spawn(move || {
-		let peer_manager = PeerManager::new(site_manager_addr);
+		let peer_manager = PeerManager::new(site_manager_addr, connection_limit, idle_timeout);
 		let peer_manager_system = System::new("Peer manager");
 		let peer_manager_addr = peer_manager.start();
 		if sender.send(peer_manager_addr).is_err() {
@@ -34,16 +52,30 @@ pub struct PeerID(String);
 pub struct PeerManager {
 	site_manager: Addr<SiteManager>,
 	peers: HashMap<String, Addr<Peer>>,
+	connection_limit: ConnectionLimit,
+	// Connections without requests for this long are closed
+	idle_timeout: Duration,
 }
 
 impl PeerManager {
-	pub fn new(site_manager: Addr<SiteManager>) -> PeerManager {
+	pub fn new(
+		site_manager: Addr<SiteManager>,
+		connection_limit: ConnectionLimit,
+		idle_timeout: Duration,
+	) -> PeerManager {
 		PeerManager {
 			site_manager,
 			peers: HashMap::new(),
+			connection_limit,
+			idle_timeout,
 		}
 	}
-	fn add(&mut self, peer_id: String, address: PeerAddress) -> Result<Addr<Peer>, Error> {
+	fn add(
+		&mut self,
+		peer_id: String,
+		address: PeerAddress,
+		manager: Addr<PeerManager>,
+	) -> Result<Addr<Peer>, Error> {
 		if let Some(addr) = self.peers.get(&peer_id) {
 			trace!("Peer {} was already known", &peer_id);
 			// TODO: update the address
@@ -53,7 +85,9 @@ impl PeerManager {
 				"Spinning up actor for peer {} with address {}",
 				&peer_id, address
 			);
-			let peer = Peer::new(address);
+			let mut peer = Peer::new(address);
+			peer.peer_manager = Some(manager);
+			peer.connection_limit = Some(self.connection_limit.clone());
 			let addr = peer.start();
 			self.peers.insert(peer_id, addr.clone());
 			Ok(addr)
@@ -68,10 +102,86 @@ impl PeerManager {
 			Err(Error::MissingError)
 		}
 	}
+	// Asks all peers for their status to close idle and surplus connections
+	fn reap(&mut self, ctx: &mut Context<Self>) {
+		let requests = self.peers.iter().map(|(peer_id, addr)| {
+			let peer_id = peer_id.clone();
+			addr
+				.send(PeerStatusRequest {})
+				.map(move |result| (peer_id, result))
+		});
+		let reaper = join_all(requests)
+			.into_actor(self)
+			.map(|results, act, _ctx| {
+				let statuses = results
+					.into_iter()
+					.filter_map(|(peer_id, result)| match result {
+						Ok(Ok(status)) => Some((peer_id, status)),
+						_ => {
+							// The actor is gone, forget about it
+							act.peers.remove(&peer_id);
+							None
+						}
+					})
+					.collect();
+				act.evict(statuses);
+			});
+		ctx.spawn(reaper);
+	}
+	fn evict(&mut self, statuses: Vec<(String, PeerStatus)>) {
+		let forget_after = Duration::seconds(FORGET_AFTER);
+		let mut connected = vec![];
+		for (peer_id, status) in statuses {
+			let idle = status.idle_time;
+			if status.connected {
+				if idle.map_or(false, |idle| idle > self.idle_timeout) {
+					self.disconnect(&peer_id, false);
+				} else {
+					connected.push((peer_id, status.stats.score()));
+				}
+			} else if status.stats.score() < 0.0 && idle.map_or(false, |idle| idle > forget_after) {
+				self.disconnect(&peer_id, true);
+			}
+		}
+
+		// Close the worst connections first once the limit is reached
+		let limit = self.connection_limit.limit();
+		if connected.len() < limit {
+			return;
+		}
+		let keep = limit - (limit as f64 * CONNECTION_HEADROOM).ceil() as usize;
+		connected.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+		let surplus = connected.len() - keep;
+		info!(
+			"{} of {} connections open, closing the {} worst",
+			connected.len(),
+			limit,
+			surplus
+		);
+		for (peer_id, _) in connected.into_iter().take(surplus) {
+			self.disconnect(&peer_id, false);
+		}
+	}
+	// Closes the connection to a peer, stopping its actor and
+	// removing it from the peer manager if `forget` is set
+	fn disconnect(&mut self, peer_id: &str, forget: bool) {
+		let addr = if forget {
+			self.peers.remove(peer_id)
+		} else {
+			self.peers.get(peer_id).cloned()
+		};
+		if let Some(addr) = addr {
+			addr.do_send(Disconnect { stop: forget });
+		}
+	}
 }
 
 impl Actor for PeerManager {
 	type Context = Context<Self>;
+
+	fn started(&mut self, ctx: &mut Context<Self>) {
+		ctx.run_interval(REAP_INTERVAL, |act, ctx| act.reap(ctx));
+	}
 }
 
 #[derive(Debug)]
@@ -106,15 +216,37 @@ impl Message for UpdatePeer {
 impl Handler<UpdatePeer> for PeerManager {
 	type Result = Result<(), Error>;
 
-	fn handle(&mut self, msg: UpdatePeer, _ctx: &mut Context<Self>) -> Self::Result {
-		let addr = self.add(msg.peer_id.clone(), msg.address)?;
+	fn handle(&mut self, msg: UpdatePeer, ctx: &mut Context<Self>) -> Self::Result {
+		// Peers are known by their address, as peers received through pex
+		// come without the peer id trackers and local discovery send along
+		let address = msg.address.to_string();
+		if msg.peer_id != address {
+			trace!("Peer {} is at {}", msg.peer_id, address);
+		}
+		let addr = self.add(address.clone(), msg.address, ctx.address())?;
 		self
 			.site_manager
 			.do_send(crate::site::site_manager::AddPeer {
-				peer_id: msg.peer_id,
+				peer_id: address.clone(),
 				peer_addr: addr,
+				address,
 				sites: msg.sites,
 			});
 		Ok(())
 	}
 }
+
+// Sends an UpdatePeer message for every address that can be parsed,
+// using the address itself as the peer's id
+pub fn update_peers(peer_manager: &Addr<PeerManager>, addresses: &[String], site: &SiteAddress) {
+	for address in addresses {
+		match PeerAddress::parse(address.clone()) {
+			Ok(peer_address) => peer_manager.do_send(UpdatePeer {
+				address: peer_address,
+				peer_id: address.clone(),
+				sites: vec![site.get_address_hash()],
+			}),
+			Err(_) => trace!("Could not parse peer address {}", address),
+		}
+	}
+}
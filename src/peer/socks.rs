@@ -0,0 +1,147 @@
+use crate::error::Error;
+use std::io::{Read, Write};
+
+// Tor's SOCKS5 proxy, used to reach .onion peers
+pub const TOR_PROXY: &str = "127.0.0.1:9050";
+// I2P's SOCKS5 proxy, used to reach .i2p peers
+pub const I2P_PROXY: &str = "127.0.0.1:4447";
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+// Host part of a host:port address
+pub fn host(address: &str) -> &str {
+	address.rsplit_once(':').map_or(address, |(host, _)| host)
+}
+
+// Proxy the address has to be reached through, None if it's reached directly
+pub fn proxy_for(address: &str) -> Option<&'static str> {
+	let host = host(address);
+	if host.ends_with(".onion") {
+		Some(TOR_PROXY)
+	} else if host.ends_with(".i2p") {
+		Some(I2P_PROXY)
+	} else {
+		None
+	}
+}
+
+// Asks the proxy on the other end of the socket to connect to the host:port
+// address, afterwards the socket is connected to the address itself
+pub fn connect<S: Read + Write>(socket: &mut S, address: &str) -> Result<(), Error> {
+	let host = host(address);
+	let port: u16 = match address.rsplit_once(':').map(|(_, port)| port.parse()) {
+		Some(Ok(port)) => port,
+		_ => return Err(Error::ProxyError(format!("No port in {}", address))),
+	};
+	if host.len() > u8::MAX as usize {
+		return Err(Error::ProxyError(format!("Host name too long: {}", host)));
+	}
+
+	socket.write_all(&[VERSION, 1, NO_AUTH])?;
+	let mut reply = [0u8; 2];
+	socket.read_exact(&mut reply)?;
+	if reply != [VERSION, NO_AUTH] {
+		return Err(Error::ProxyError(String::from(
+			"Proxy requires authentication",
+		)));
+	}
+
+	let mut request = vec![VERSION, CMD_CONNECT, 0, ATYP_DOMAIN, host.len() as u8];
+	request.extend_from_slice(host.as_bytes());
+	request.extend_from_slice(&port.to_be_bytes());
+	socket.write_all(&request)?;
+	let mut reply = [0u8; 4];
+	socket.read_exact(&mut reply)?;
+	if reply[0] != VERSION {
+		return Err(Error::ProxyError(String::from("Invalid proxy reply")));
+	}
+	if reply[1] != 0 {
+		return Err(Error::ProxyError(format!(
+			"Proxy could not connect to {}, reply {}",
+			address, reply[1]
+		)));
+	}
+	// Skip the address the proxy connected from
+	let len = match reply[3] {
+		ATYP_IPV4 => 4,
+		ATYP_IPV6 => 16,
+		ATYP_DOMAIN => {
+			let mut len = [0u8; 1];
+			socket.read_exact(&mut len)?;
+			len[0] as usize
+		}
+		atyp => {
+			return Err(Error::ProxyError(format!(
+				"Unknown address type {} in proxy reply",
+				atyp
+			)))
+		}
+	};
+	let mut bound = vec![0u8; len + 2];
+	socket.read_exact(&mut bound)?;
+	Ok(())
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+
+	// Proxy answering with the given bytes
+	struct Proxy {
+		replies: Cursor<Vec<u8>>,
+		received: Vec<u8>,
+	}
+
+	impl Read for Proxy {
+		fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+			self.replies.read(buf)
+		}
+	}
+
+	impl Write for Proxy {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			self.received.write(buf)
+		}
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+
+	fn proxy(replies: &[u8]) -> Proxy {
+		Proxy {
+			replies: Cursor::new(replies.to_vec()),
+			received: vec![],
+		}
+	}
+
+	#[test]
+	fn test_proxy_for() {
+		assert_eq!(proxy_for("abc.onion:15441"), Some(TOR_PROXY));
+		assert_eq!(proxy_for("abc.b32.i2p:15441"), Some(I2P_PROXY));
+		assert_eq!(proxy_for("1.2.3.4:15441"), None);
+		assert_eq!(proxy_for("[::1]:15441"), None);
+	}
+
+	#[test]
+	fn test_connect() {
+		let mut socket = proxy(&[5, 0, 5, 0, 0, 1, 127, 0, 0, 1, 0, 80]);
+		assert!(connect(&mut socket, "abc.onion:15441").is_ok());
+		let mut expected = vec![5, 1, 0, 5, 1, 0, 3, 9];
+		expected.extend_from_slice(b"abc.onion");
+		expected.extend_from_slice(&15441u16.to_be_bytes());
+		assert_eq!(socket.received, expected);
+
+		let mut socket = proxy(&[5, 0, 5, 4, 0, 1, 0, 0, 0, 0, 0, 0]);
+		assert!(connect(&mut socket, "abc.onion:15441").is_err());
+		let mut socket = proxy(&[5, 2]);
+		assert!(connect(&mut socket, "abc.onion:15441").is_err());
+		assert!(connect(&mut proxy(&[]), "abc.onion").is_err());
+	}
+}
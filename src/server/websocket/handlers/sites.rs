@@ -1,10 +1,17 @@
 use super::super::error::Error;
 use super::super::request::Command;
-use super::super::response::Message;
+use super::super::response::{Message, Notification, Redirect};
 use super::super::ZeruWebsocket;
+use crate::site::site_manager::{AddSite, DeleteSite, SetServing, VerifySite};
+use crate::site::{address::Address, SiteClone, SitePublish, SiteSetLimit, SiteSettingsRequest};
+use crate::user::user_manager::NewSiteRequest;
+use actix::{ActorFuture, AsyncContext, WrapFuture};
 use actix_web_actors::ws::WebsocketContext;
 use futures::executor::block_on;
 use log::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::str::FromStr;
 
 pub fn handle_site_info(
 	ws: &ZeruWebsocket,
@@ -26,6 +33,343 @@ pub fn handle_site_info(
 	command.respond(result)
 }
 
+pub fn handle_site_update(
+	ws: &ZeruWebsocket,
+	ctx: &mut WebsocketContext<ZeruWebsocket>,
+	command: &Command,
+) -> Result<Message, Error> {
+	info!("Handling SiteUpdate for {}", ws.address.get_address_short());
+	match block_on(ws.site_addr.send(crate::site::SiteUpdateRequest {})) {
+		Ok(Ok(_)) => command.respond(String::from("Updated")),
+		_ => Err(Error {}),
+	}
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct SitePublishParams {
+	inner_path: String,
+	privatekey: Option<String>,
+	sign: bool,
+}
+
+impl Default for SitePublishParams {
+	fn default() -> SitePublishParams {
+		SitePublishParams {
+			inner_path: String::from("content.json"),
+			privatekey: None,
+			sign: true,
+		}
+	}
+}
+
+// Publishing takes a while, so the response is sent once it's done
+// and the progress is reported to the wrapper in the meantime
+pub fn handle_site_publish(
+	ws: &ZeruWebsocket,
+	ctx: &mut WebsocketContext<ZeruWebsocket>,
+	command: &Command,
+) -> Result<(), Error> {
+	let params: SitePublishParams = match &command.params {
+		serde_json::Value::Null => SitePublishParams::default(),
+		params => serde_json::from_value(params.clone())?,
+	};
+	info!(
+		"Handling SitePublish for zero://{}/{}",
+		ws.address.get_address_short(),
+		params.inner_path
+	);
+	let id = command.id;
+	let refuse = |ctx: &mut WebsocketContext<ZeruWebsocket>, error: &str| -> Result<(), Error> {
+		let j = serde_json::to_string(&Message::new(id, json!({ "error": error })))?;
+		ctx.text(j);
+		Ok(())
+	};
+	if params.sign && params.privatekey.is_some() {
+		// TODO: sign the content.json once SiteSign is implemented
+		return refuse(
+			ctx,
+			"Signing on publish is not supported, sign the content.json first.",
+		);
+	}
+	match block_on(ws.site_addr.send(SiteSettingsRequest {})) {
+		Ok(Ok(settings)) if settings.own => {}
+		Ok(Ok(_)) => return refuse(ctx, "Forbidden, you can only publish your own sites."),
+		_ => return Err(Error {}),
+	}
+	let msg = SitePublish {
+		inner_path: params.inner_path,
+		progress: Some(ctx.address().recipient()),
+	};
+	let publish = ws
+		.site_addr
+		.send(msg)
+		.into_actor(ws)
+		.map(move |result, _act, ctx| {
+			let result = match result {
+				Ok(Ok(0)) => {
+					let text = "No peers found, but your content is ready to access.";
+					if let Ok(j) = serde_json::to_string(&Notification::new("info", text, 5000)) {
+						ctx.text(j);
+					}
+					json!("ok")
+				}
+				Ok(Ok(_)) => json!("ok"),
+				Ok(Err(err)) => json!({ "error": format!("Content publish failed: {:?}", err) }),
+				Err(_) => json!({ "error": "Content publish failed." }),
+			};
+			if let Ok(j) = serde_json::to_string(&Message::new(id, result)) {
+				ctx.text(j);
+			}
+		});
+	ctx.spawn(publish);
+	Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct SiteCloneParams {
+	address: String,
+	root_inner_path: String,
+	redirect: bool,
+}
+
+impl Default for SiteCloneParams {
+	fn default() -> SiteCloneParams {
+		SiteCloneParams {
+			address: String::new(),
+			root_inner_path: String::new(),
+			redirect: true,
+		}
+	}
+}
+
+// Clone a cloneable site into a new site owned by the user,
+// the params are either named or in the order of the reference client
+pub fn handle_site_clone(
+	ws: &ZeruWebsocket,
+	ctx: &mut WebsocketContext<ZeruWebsocket>,
+	command: &Command,
+) -> Result<Message, Error> {
+	let params: SiteCloneParams = match &command.params {
+		serde_json::Value::Array(params) => SiteCloneParams {
+			address: params.get(0).and_then(|p| p.as_str()).unwrap_or("").into(),
+			root_inner_path: params.get(1).and_then(|p| p.as_str()).unwrap_or("").into(),
+			..SiteCloneParams::default()
+		},
+		serde_json::Value::String(address) => SiteCloneParams {
+			address: address.clone(),
+			..SiteCloneParams::default()
+		},
+		params => serde_json::from_value(params.clone())?,
+	};
+	if !params.address.is_empty() && params.address != ws.address.to_string() {
+		return command.respond(json!({ "error": "Only the current site can be cloned" }));
+	}
+	info!(
+		"Handling SiteClone for {} ({})",
+		ws.address.get_address_short(),
+		params.root_inner_path
+	);
+	let (address, address_index, privatekey) = match block_on(ws.user_manager.send(NewSiteRequest {
+		address: String::new(),
+	})) {
+		Ok(Ok(site_data)) => site_data,
+		_ => return command.respond(json!({ "error": "Could not create a key for the new site" })),
+	};
+	let new_address = match Address::from_str(&address) {
+		Ok(new_address) => new_address,
+		Err(_) => return Err(Error {}),
+	};
+	let msg = SiteClone {
+		address: new_address.clone(),
+		address_index,
+		privatekey,
+		root_inner_path: params.root_inner_path,
+	};
+	match block_on(ws.site_addr.send(msg)) {
+		Ok(Ok(())) => {}
+		Ok(Err(err)) => return command.respond(json!({ "error": format!("{:?}", err) })),
+		Err(_) => return Err(Error {}),
+	}
+	let msg = AddSite {
+		address: new_address,
+		own: true,
+	};
+	match block_on(ws.site_manager.send(msg)) {
+		Ok(Ok(_)) => {}
+		_ => return command.respond(json!({ "error": "Could not add the new site" })),
+	}
+	if params.redirect {
+		if let Ok(j) = serde_json::to_string(&Redirect::new(&format!("/{}", address))) {
+			ctx.text(j);
+		}
+	}
+	command.respond(json!({ "address": address }))
+}
+
+// Site address of admin commands, either the first param or named address
+fn address_param(command: &Command) -> Option<Address> {
+	let param = match &command.params {
+		serde_json::Value::Array(params) => params.get(0),
+		serde_json::Value::Object(params) => params.get("address"),
+		param => Some(param),
+	};
+	Address::from_str(param?.as_str()?).ok()
+}
+
+// Whether the calling site has the ADMIN permission,
+// which commands acting on other sites require
+fn is_admin(ws: &ZeruWebsocket) -> Result<bool, Error> {
+	match block_on(ws.site_addr.send(SiteSettingsRequest {})) {
+		Ok(Ok(settings)) => Ok(settings.permissions.iter().any(|p| p == "ADMIN")),
+		_ => Err(Error {}),
+	}
+}
+
+// Delete a site with all of its files, admin sites only
+pub fn handle_site_delete(
+	ws: &ZeruWebsocket,
+	ctx: &mut WebsocketContext<ZeruWebsocket>,
+	command: &Command,
+) -> Result<Message, Error> {
+	if !is_admin(ws)? {
+		return command.respond(json!({ "error": "Forbidden, only admin sites can delete sites." }));
+	}
+	let address = match address_param(command) {
+		Some(address) => address,
+		None => return command.respond(json!({ "error": "Invalid address" })),
+	};
+	info!("Handling SiteDelete for {}", address.get_address_short());
+	match block_on(ws.site_manager.send(DeleteSite { address })) {
+		Ok(Ok(())) => command.respond(String::from("Deleted")),
+		Ok(Err(crate::error::Error::MissingError)) => {
+			command.respond(json!({ "error": "Unknown site" }))
+		}
+		Ok(Err(err)) => command.respond(json!({ "error": format!("{:?}", err) })),
+		Err(_) => Err(Error {}),
+	}
+}
+
+// Pause or resume a site, paused sites keep their files, admin sites only
+pub fn handle_site_set_serving(
+	ws: &ZeruWebsocket,
+	ctx: &mut WebsocketContext<ZeruWebsocket>,
+	command: &Command,
+	serving: bool,
+) -> Result<Message, Error> {
+	if !is_admin(ws)? {
+		return command.respond(json!({
+			"error": "Forbidden, only admin sites can pause and resume sites."
+		}));
+	}
+	let address = match address_param(command) {
+		Some(address) => address,
+		None => return command.respond(json!({ "error": "Invalid address" })),
+	};
+	info!(
+		"Handling {:?} for {}",
+		command.cmd,
+		address.get_address_short()
+	);
+	match block_on(ws.site_manager.send(SetServing { address, serving })) {
+		Ok(Ok(())) if serving => command.respond(String::from("Resumed")),
+		Ok(Ok(())) => command.respond(String::from("Paused")),
+		Ok(Err(crate::error::Error::MissingError)) => {
+			command.respond(json!({ "error": "Unknown site" }))
+		}
+		Ok(Err(err)) => command.respond(json!({ "error": format!("{:?}", err) })),
+		Err(_) => Err(Error {}),
+	}
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SiteVerifyParams {
+	address: Option<String>,
+	delete_unlisted: bool,
+}
+
+// Verify the files of a site, the current one unless an address is given,
+// and respond with a report of the missing, corrupt and unlisted files.
+// Other sites can only be verified and unlisted files only be deleted by admin sites
+pub fn handle_site_verify(
+	ws: &ZeruWebsocket,
+	ctx: &mut WebsocketContext<ZeruWebsocket>,
+	command: &Command,
+) -> Result<Message, Error> {
+	let params: SiteVerifyParams = match &command.params {
+		serde_json::Value::Object(_) => serde_json::from_value(command.params.clone())?,
+		_ => SiteVerifyParams::default(),
+	};
+	let address = match params.address {
+		Some(address) => match Address::from_str(&address) {
+			Ok(address) => address,
+			Err(_) => return command.respond(json!({ "error": "Invalid address" })),
+		},
+		None => ws.address.clone(),
+	};
+	if (address != ws.address || params.delete_unlisted) && !is_admin(ws)? {
+		return command.respond(json!({
+			"error": "Forbidden, only admin sites can verify other sites or delete unlisted files."
+		}));
+	}
+	info!("Handling SiteVerify for {}", address.get_address_short());
+	let message = VerifySite {
+		address,
+		delete_unlisted: params.delete_unlisted,
+	};
+	match block_on(ws.site_manager.send(message)) {
+		Ok(Ok(report)) => command.respond(report),
+		Ok(Err(crate::error::Error::MissingError)) => {
+			command.respond(json!({ "error": "Unknown site" }))
+		}
+		Ok(Err(err)) => command.respond(json!({ "error": format!("{:?}", err) })),
+		Err(_) => Err(Error {}),
+	}
+}
+
+// Set the size limit of the current site, admin sites only as sites
+// shouldn't raise their own limit without the user's approval.
+// The limit in MB is either the first param or named size_limit
+pub fn handle_site_set_limit(
+	ws: &ZeruWebsocket,
+	ctx: &mut WebsocketContext<ZeruWebsocket>,
+	command: &Command,
+) -> Result<Message, Error> {
+	if !is_admin(ws)? {
+		return command.respond(json!({
+			"error": "Forbidden, only admin sites can change size limits."
+		}));
+	}
+	let param = match &command.params {
+		serde_json::Value::Array(params) => params.get(0),
+		serde_json::Value::Object(params) => params.get("size_limit"),
+		param => Some(param),
+	};
+	let size_limit = match param {
+		Some(serde_json::Value::Number(n)) => n.as_u64(),
+		Some(serde_json::Value::String(s)) => s.parse().ok(),
+		_ => None,
+	};
+	let size_limit = match size_limit {
+		Some(size_limit) => size_limit as usize,
+		None => return command.respond(json!({ "error": "Invalid size limit" })),
+	};
+	info!(
+		"Handling SiteSetLimit of {}MB for {}",
+		size_limit,
+		ws.address.get_address_short()
+	);
+	match block_on(ws.site_addr.send(SiteSetLimit { size_limit })) {
+		Ok(Ok(())) => command.respond(String::from("ok")),
+		Ok(Err(crate::error::Error::SizeLimit)) => {
+			command.respond(json!({ "error": "Size limit is below the size of the site" }))
+		}
+		_ => Err(Error {}),
+	}
+}
+
 pub fn handle_site_list(
 	ws: &ZeruWebsocket,
 	ctx: &mut WebsocketContext<ZeruWebsocket>,
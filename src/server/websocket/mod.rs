@@ -4,8 +4,9 @@ pub mod request;
 pub mod response;
 
 use crate::site::site_manager::{Lookup, SiteManager};
+use crate::site::PublishProgress;
 use crate::user::user_manager::{UserManager, UserRequest};
-use actix::{Actor, Addr, StreamHandler};
+use actix::{Actor, Addr, Handler, StreamHandler};
 use actix_web::{
 	web::{Data, Payload, Query},
 	HttpRequest, HttpResponse, Result,
@@ -14,7 +15,7 @@ use actix_web_actors::ws;
 use futures::executor::block_on;
 use log::*;
 use request::{Command, CommandType::*};
-use response::Message;
+use response::{Message, Progress};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -101,6 +102,21 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ZeruWebsocket {
 	}
 }
 
+impl Handler<PublishProgress> for ZeruWebsocket {
+	type Result = ();
+
+	fn handle(&mut self, msg: PublishProgress, ctx: &mut Self::Context) -> Self::Result {
+		let text = format!(
+			"Content published to {}/{} peers.",
+			msg.published, msg.limit
+		);
+		let percent = 100 * msg.published / msg.limit.max(1);
+		if let Ok(j) = serde_json::to_string(&Progress::new("publish", &text, percent)) {
+			ctx.text(j);
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct WrapperCommand {
 	cmd: WrapperCommandType,
@@ -226,6 +242,14 @@ impl ZeruWebsocket {
 			ServerInfo => handle_server_info(ctx, command),
 			SiteInfo => handlers::sites::handle_site_info(self, ctx, command),
 			SiteList => handlers::sites::handle_site_list(self, ctx, command),
+			SiteUpdate => handlers::sites::handle_site_update(self, ctx, command),
+			SitePublish => return handlers::sites::handle_site_publish(self, ctx, command),
+			SiteSetLimit => handlers::sites::handle_site_set_limit(self, ctx, command),
+			SiteClone => handlers::sites::handle_site_clone(self, ctx, command),
+			SiteDelete => handlers::sites::handle_site_delete(self, ctx, command),
+			SitePause => handlers::sites::handle_site_set_serving(self, ctx, command, false),
+			SiteResume => handlers::sites::handle_site_set_serving(self, ctx, command, true),
+			SiteVerify => handlers::sites::handle_site_verify(self, ctx, command),
 			OptionalLimitStats => handlers::sites::handle_optional_limit_stats(self, ctx, command),
 			FileGet => handlers::files::handle_file_get(self, ctx, command),
 			UserGetSettings => handlers::users::handle_user_get_settings(self, ctx, command),
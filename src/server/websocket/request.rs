@@ -76,9 +76,11 @@ pub enum CommandType {
 	ServerShutdown,
 	ServerUpdate,
 	SiteClone,
+	SiteDelete,
 	SiteList,
 	SitePause,
 	SiteResume,
+	SiteVerify,
 
 	ServerErrors,
 	UserGetSettings,
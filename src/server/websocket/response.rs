@@ -15,6 +15,9 @@ pub enum MessageType {
 	Response,
 	Error,
 	Ping,
+	Notification,
+	Progress,
+	Redirect,
 }
 
 impl Message {
@@ -26,3 +29,51 @@ impl Message {
 		}
 	}
 }
+
+// Notification shown by the wrapper, params are the type, text and timeout in milliseconds
+#[derive(Serialize, Deserialize)]
+pub struct Notification {
+	cmd: MessageType,
+	params: (String, String, usize),
+}
+
+impl Notification {
+	pub fn new(kind: &str, text: &str, timeout: usize) -> Notification {
+		Notification {
+			cmd: MessageType::Notification,
+			params: (String::from(kind), String::from(text), timeout),
+		}
+	}
+}
+
+// Makes the wrapper navigate to the url in params
+#[derive(Serialize, Deserialize)]
+pub struct Redirect {
+	cmd: MessageType,
+	params: String,
+}
+
+impl Redirect {
+	pub fn new(url: &str) -> Redirect {
+		Redirect {
+			cmd: MessageType::Redirect,
+			params: String::from(url),
+		}
+	}
+}
+
+// Progress bar shown by the wrapper, params are its id, text and percentage done
+#[derive(Serialize, Deserialize)]
+pub struct Progress {
+	cmd: MessageType,
+	params: (String, String, usize),
+}
+
+impl Progress {
+	pub fn new(id: &str, text: &str, percent: usize) -> Progress {
+		Progress {
+			cmd: MessageType::Progress,
+			params: (String::from(id), String::from(text), percent),
+		}
+	}
+}
@@ -0,0 +1,293 @@
+use crate::error::Error;
+use regex::Regex;
+use std::collections::BTreeMap;
+use zerucontent::user_contents::Permission;
+use zerucontent::{Content, Include, UserContents};
+
+// What a content.json file other than the root one may contain and who
+// may sign it, taken from the include or user_contents of a parent
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Rules {
+	pub signers: Vec<String>,
+	pub signers_required: usize,
+	pub files_allowed: String,
+	pub files_allowed_optional: String,
+	pub includes_allowed: bool,
+	// Maximum size of the content.json and its files in bytes, 0 if unlimited
+	pub max_size: usize,
+	// Set for user content, which needs a certificate
+	pub cert: Option<CertRules>,
+}
+
+// Certificates accepted for user content
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CertRules {
+	// Auth address of the user, the directory the content.json is in
+	pub user_address: String,
+	// Addresses allowed to sign certificates by certificate domain
+	pub cert_signers: BTreeMap<String, Vec<String>>,
+	// Certificate domains matching this are their own signer
+	pub cert_signers_pattern: String,
+}
+
+impl Rules {
+	pub fn from_include(include: &Include) -> Rules {
+		Rules {
+			signers: include.signers.clone(),
+			signers_required: include.signers_required as usize,
+			files_allowed: include.files_allowed.clone(),
+			files_allowed_optional: include.files_allowed_optional.clone(),
+			includes_allowed: include.includes_allowed,
+			max_size: include.max_size as usize,
+			cert: None,
+		}
+	}
+	// Rules for the content.json of a user, the ones set for the user by
+	// `permissions` are improved by all matching `permission_rules`
+	pub fn from_user_contents(
+		user_contents: &UserContents,
+		inner_path: &str,
+		content: &Content,
+	) -> Option<Rules> {
+		let mut dirs = inner_path.rsplit('/').skip(1);
+		let user_address = dirs.next()?.to_string();
+		let user_urn = format!("{}/{}", content.cert_auth_type, content.cert_user_id);
+
+		let mut rules = Rules::default();
+		let mut banned = false;
+		match user_contents.permissions.get(&content.cert_user_id) {
+			Some(Permission::Rules(permission)) => {
+				rules.files_allowed = permission.files_allowed.clone();
+				rules.files_allowed_optional = permission.files_allowed_optional.clone();
+				rules.max_size = permission.max_size;
+			}
+			Some(Permission::Banned(_)) => banned = true,
+			None => {}
+		}
+		for (pattern, permission) in user_contents.permission_rules.iter() {
+			if !matches_start(pattern, &user_urn) {
+				continue;
+			}
+			if permission.max_size > rules.max_size {
+				rules.max_size = permission.max_size;
+			}
+			if permission.files_allowed.len() > rules.files_allowed.len() {
+				rules.files_allowed = permission.files_allowed.clone();
+			}
+			if permission.files_allowed_optional.len() > rules.files_allowed_optional.len() {
+				rules.files_allowed_optional = permission.files_allowed_optional.clone();
+			}
+		}
+		// Banned users can only have their content signed by the site owner
+		if !banned {
+			rules.signers.push(user_address.clone());
+		}
+		rules.signers_required = 1;
+		rules.cert = Some(CertRules {
+			user_address,
+			cert_signers: user_contents.cert_signers.clone(),
+			cert_signers_pattern: user_contents.cert_signers_pattern.clone(),
+		});
+		Some(rules)
+	}
+	// Check a received content.json of `size` bytes against the rules,
+	// the site owner may always sign
+	pub fn verify(&self, site_address: &str, content: &Content, size: usize) -> Result<(), Error> {
+		if !self.includes_allowed && !content.includes.is_empty() {
+			return Err(invalid("Includes not allowed"));
+		}
+		for inner_path in content.files.keys() {
+			if !self.file_allowed(&self.files_allowed, inner_path) {
+				return Err(invalid(&format!("File not allowed: {}", inner_path)));
+			}
+		}
+		for inner_path in content.files_optional.keys() {
+			if !self.file_allowed(&self.files_allowed_optional, inner_path) {
+				return Err(invalid(&format!(
+					"Optional file not allowed: {}",
+					inner_path
+				)));
+			}
+		}
+		// Sizes come from the user's content.json, so they may add up past usize
+		let total_size = content
+			.files
+			.values()
+			.try_fold(size, |total, file| total.checked_add(file.size))
+			.ok_or_else(|| invalid("Size of the files is too big"))?;
+		if self.max_size > 0 && total_size > self.max_size {
+			return Err(invalid(&format!(
+				"Size {} exceeds the limit of {}",
+				total_size, self.max_size
+			)));
+		}
+
+		let signed_by_owner = content.verify(String::from(site_address));
+		let valid_signs = content
+			.signs
+			.keys()
+			.filter(|signer| signer.as_str() != site_address && self.signers.contains(*signer))
+			.filter(|signer| content.verify(signer.to_string()))
+			.count()
+			+ signed_by_owner as usize;
+		if valid_signs < self.signers_required.max(1) {
+			return Err(invalid(&format!(
+				"{} valid signs, {} required",
+				valid_signs,
+				self.signers_required.max(1)
+			)));
+		}
+		match &self.cert {
+			Some(cert) if !signed_by_owner => cert.verify(content),
+			_ => Ok(()),
+		}
+	}
+	// No pattern allows nothing for user content and everything for includes
+	fn file_allowed(&self, pattern: &str, inner_path: &str) -> bool {
+		if pattern.is_empty() {
+			return self.cert.is_none();
+		}
+		match Regex::new(&format!("^(?:{})$", pattern)) {
+			Ok(regex) => regex.is_match(inner_path),
+			Err(_) => false,
+		}
+	}
+}
+
+impl CertRules {
+	// The certificate has to be signed by a signer accepted for its domain
+	pub fn verify(&self, content: &Content) -> Result<(), Error> {
+		if self.cert_signers.is_empty() && self.cert_signers_pattern.is_empty() {
+			return Ok(());
+		}
+		let mut parts = content.cert_user_id.split('@');
+		let (name, domain) = match (parts.next(), parts.next(), parts.next()) {
+			(Some(name), Some(domain), None) => (name, domain),
+			_ => return Err(invalid("Invalid cert_user_id")),
+		};
+		let cert_signers = match self.cert_signers.get(domain) {
+			Some(signers) => signers.clone(),
+			None
+				if !self.cert_signers_pattern.is_empty()
+					&& matches_start(&self.cert_signers_pattern, domain) =>
+			{
+				vec![String::from(domain)]
+			}
+			None => return Err(invalid(&format!("Invalid cert signer: {}", domain))),
+		};
+		let cert = format!("{}#{}/{}", self.user_address, content.cert_auth_type, name);
+		let valid = cert_signers
+			.iter()
+			.any(|signer| zerucrypt::verify(cert.as_bytes(), signer, &content.cert_sign).is_ok());
+		if !valid {
+			return Err(invalid("Invalid cert"));
+		}
+		Ok(())
+	}
+}
+
+fn invalid(reason: &str) -> Error {
+	Error::InvalidContent(String::from(reason))
+}
+
+// Patterns are matched from the start of the text like the reference client does
+pub fn matches_start(pattern: &str, text: &str) -> bool {
+	match Regex::new(&format!("^(?:{})", pattern)) {
+		Ok(regex) => regex.is_match(text),
+		Err(_) => false,
+	}
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::*;
+	use zerucontent::user_contents::PermissionRules;
+	use zerucontent::File as ContentFile;
+
+	fn user_contents() -> UserContents {
+		let mut user_contents = UserContents::default();
+		user_contents.permission_rules.insert(
+			String::from(".*"),
+			PermissionRules {
+				files_allowed: String::from("data.json"),
+				max_size: 10000,
+				..PermissionRules::default()
+			},
+		);
+		user_contents.permission_rules.insert(
+			String::from("bitid/.*@zeroid.bit"),
+			PermissionRules {
+				max_size: 40000,
+				..PermissionRules::default()
+			},
+		);
+		user_contents
+	}
+
+	fn user_content(cert_user_id: &str) -> Content {
+		let mut content = Content::default();
+		content.cert_auth_type = String::from("bitid");
+		content.cert_user_id = String::from(cert_user_id);
+		content
+	}
+
+	#[test]
+	fn test_user_rules() {
+		let inner_path = "data/users/1J3rJ8ecnwH2EPYa6MrgZttBNc61ACFiCj/content.json";
+		let content = user_content("nofish@zeroid.bit");
+		let rules = Rules::from_user_contents(&user_contents(), inner_path, &content).unwrap();
+		assert_eq!(rules.signers, vec!["1J3rJ8ecnwH2EPYa6MrgZttBNc61ACFiCj"]);
+		assert_eq!(rules.files_allowed, "data.json");
+		assert_eq!(rules.max_size, 40000);
+
+		let mut banned = user_contents();
+		banned
+			.permissions
+			.insert(String::from("nofish@zeroid.bit"), Permission::Banned(false));
+		let rules = Rules::from_user_contents(&banned, inner_path, &content).unwrap();
+		assert!(rules.signers.is_empty());
+	}
+
+	#[test]
+	fn test_files_allowed() {
+		let inner_path = "data/users/1J3rJ8ecnwH2EPYa6MrgZttBNc61ACFiCj/content.json";
+		let mut content = user_content("nofish@zeroid.bit");
+		let rules = Rules::from_user_contents(&user_contents(), inner_path, &content).unwrap();
+		content.files.insert(
+			String::from("data.json"),
+			ContentFile {
+				sha512: String::new(),
+				size: 100,
+			},
+		);
+		assert!(rules.file_allowed(&rules.files_allowed, "data.json"));
+		assert!(!rules.file_allowed(&rules.files_allowed, "index.html"));
+		assert!(!rules.file_allowed(&rules.files_allowed_optional, "video.mp4"));
+		content
+			.includes
+			.insert(String::from("content.json"), Include::default());
+		match rules.verify("1HeLLo4uzjaLetFx6NH3PMwFP3qbRbTf3D", &content, 100) {
+			Err(Error::InvalidContent(_)) => {}
+			_ => panic!("Includes should not be allowed"),
+		}
+	}
+
+	#[test]
+	fn test_size_overflow() {
+		let inner_path = "data/users/1J3rJ8ecnwH2EPYa6MrgZttBNc61ACFiCj/content.json";
+		let mut content = user_content("nofish@zeroid.bit");
+		let rules = Rules::from_user_contents(&user_contents(), inner_path, &content).unwrap();
+		content.files.insert(
+			String::from("data.json"),
+			ContentFile {
+				sha512: String::new(),
+				size: usize::MAX,
+			},
+		);
+		match rules.verify("1HeLLo4uzjaLetFx6NH3PMwFP3qbRbTf3D", &content, 100) {
+			Err(Error::InvalidContent(_)) => {}
+			_ => panic!("Overflowing sizes should be refused"),
+		}
+	}
+}
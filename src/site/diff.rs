@@ -0,0 +1,97 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum DiffParam {
+	// Number of bytes kept or removed
+	Length(usize),
+	// Lines added
+	Lines(Vec<ByteBuf>),
+}
+
+// Action of a diff in the format of the reference client, "=" keeps and "-" skips
+// a number of bytes of the old version, "+" adds lines
+pub type Action = (String, DiffParam);
+
+// Diffs of the files changed in a content.json file, by path relative to it
+pub type Diffs = BTreeMap<String, Vec<Action>>;
+
+// Apply the actions of a diff to the old version of a file
+pub fn patch(old: &[u8], actions: &[Action]) -> Result<Vec<u8>, Error> {
+	let mut new = Vec::with_capacity(old.len());
+	let mut position: usize = 0;
+	for (action, param) in actions {
+		match (action.as_str(), param) {
+			("=", DiffParam::Length(len)) => {
+				let end = position
+					.checked_add(*len)
+					.filter(|end| *end <= old.len())
+					.ok_or_else(|| invalid("Diff keeps more than the old file has"))?;
+				new.extend_from_slice(&old[position..end]);
+				position = end;
+			}
+			("-", DiffParam::Length(len)) => {
+				position = position
+					.checked_add(*len)
+					.filter(|end| *end <= old.len())
+					.ok_or_else(|| invalid("Diff removes more than the old file has"))?;
+			}
+			("+", DiffParam::Lines(lines)) => {
+				for line in lines {
+					new.extend_from_slice(line);
+				}
+			}
+			_ => return Err(invalid(&format!("Unknown diff action {}", action))),
+		}
+	}
+	Ok(new)
+}
+
+fn invalid(reason: &str) -> Error {
+	Error::InvalidContent(String::from(reason))
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::*;
+
+	fn lines(lines: &[&str]) -> DiffParam {
+		DiffParam::Lines(lines.iter().map(|l| ByteBuf::from(l.as_bytes())).collect())
+	}
+
+	#[test]
+	fn test_patch() {
+		let old = b"{\n\t\"title\": \"Old\"\n}\n";
+		let actions = vec![
+			(String::from("="), DiffParam::Length(2)),
+			(String::from("-"), DiffParam::Length(16)),
+			(
+				String::from("+"),
+				lines(&["\t\"title\": \"New\",\n", "\t\"id\": 1\n"]),
+			),
+			(String::from("="), DiffParam::Length(2)),
+		];
+		assert_eq!(
+			patch(old, &actions).unwrap(),
+			b"{\n\t\"title\": \"New\",\n\t\"id\": 1\n}\n".to_vec()
+		);
+		let too_long = vec![(String::from("="), DiffParam::Length(100))];
+		assert!(patch(old, &too_long).is_err());
+		let overflow = vec![
+			(String::from("="), DiffParam::Length(1)),
+			(String::from("-"), DiffParam::Length(usize::MAX)),
+		];
+		assert!(patch(old, &overflow).is_err());
+		let overflow = vec![
+			(String::from("-"), DiffParam::Length(1)),
+			(String::from("="), DiffParam::Length(usize::MAX)),
+		];
+		assert!(patch(old, &overflow).is_err());
+		let unknown = vec![(String::from("?"), DiffParam::Length(1))];
+		assert!(patch(old, &unknown).is_err());
+	}
+}
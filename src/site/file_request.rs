@@ -1,52 +1,381 @@
-use super::address::Address;
-use std::path::{PathBuf, Path};
+use chrono::{DateTime, Duration, Utc};
+use futures::channel::oneshot;
+use std::collections::HashMap;
+use std::path::Path;
+use zerucontent::File as ContentFile;
 
-enum Priority {
-  Content(usize),
-  Site,
-  Database,
-  Required, // Files required by content.json
-  Optional, // Optional files requested by ZeroFrame
-  Default,
-  Background, // Friend's files, preloading etc.
+// Downloads are given up on after failing this many times
+pub const MAX_TRIES: usize = 5;
+// Seconds to wait before retrying a failed download, multiplied by the number of tries
+const RETRY_DELAY: i64 = 5;
+
+// Files are downloaded in this order, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+	Content(usize), // content.json files, by depth
+	Site,
+	Database,
+	Required,   // Files required by content.json
+	Optional,   // Optional files requested by ZeroFrame
+	Background, // Friend's files, preloading etc.
 }
 
 impl Priority {
-  pub fn from_path(path: &Path) -> Result<Priority, ()> {
-    if let Some(s) = path.to_str() {
-      if s == "content.json" {
-        return Ok(Priority::Content(0))
-      }
-    }
-    if let Some(file_name) = match path.file_name() {
-      None => return Err(()),
-      Some(f) => f.to_str(),
-    } {
-      let priority = match file_name {
-        "content.json" => Priority::Content(path.ancestors().count()),
-        "index.html" => Priority::Site,
-        "all.css" => Priority::Site,
-        "all.js" => Priority::Site,
-        "dbschema.json" => Priority::Database,
-        _ => Priority::Default,
-      };
-      return Ok(priority)
-    } else {
-      return Err(())
-    }
-  }
-}
-
-enum Status {
-  Waiting,
-  Downloading,
-  Paused,
-  Failed,
-}
-
-struct FileRequest {
-  address: Address,
-  path: PathBuf,
-  status: Status,
-  priority: Priority,
+	pub fn from_path(path: &Path, optional: bool) -> Result<Priority, ()> {
+		if let Some(s) = path.to_str() {
+			if s == "content.json" {
+				return Ok(Priority::Content(0));
+			}
+		}
+		if let Some(file_name) = match path.file_name() {
+			None => return Err(()),
+			Some(f) => f.to_str(),
+		} {
+			let priority = match file_name {
+				"content.json" => Priority::Content(path.ancestors().count()),
+				"index.html" => Priority::Site,
+				"all.css" => Priority::Site,
+				"all.js" => Priority::Site,
+				"dbschema.json" => Priority::Database,
+				_ if optional => Priority::Optional,
+				_ => Priority::Required,
+			};
+			Ok(priority)
+		} else {
+			Err(())
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+	Waiting,
+	Downloading,
+	Paused,
+	Failed,
+}
+
+pub struct FileRequest {
+	pub inner_path: String,
+	pub status: Status,
+	pub priority: Priority,
+	// Expected size and hash, None for content.json files
+	pub file: Option<ContentFile>,
+	pub tries: usize,
+	pub last_try: Option<DateTime<Utc>>,
+	// Peers that failed to serve the file since the last time all peers were tried
+	pub tried_peers: Vec<String>,
+	// Notified with the result once the file is downloaded or given up on
+	waiters: Vec<oneshot::Sender<bool>>,
+}
+
+impl FileRequest {
+	pub fn new(inner_path: &str, priority: Priority, file: Option<ContentFile>) -> FileRequest {
+		FileRequest {
+			inner_path: String::from(inner_path),
+			status: Status::Waiting,
+			priority,
+			file,
+			tries: 0,
+			last_try: None,
+			tried_peers: Vec::new(),
+			waiters: Vec::new(),
+		}
+	}
+	// Waiting and not backing off from a failed try
+	fn can_start(&self, now: DateTime<Utc>) -> bool {
+		if self.status != Status::Waiting {
+			return false;
+		}
+		match self.last_try {
+			Some(last_try) => now - last_try >= Duration::seconds(RETRY_DELAY * self.tries as i64),
+			None => true,
+		}
+	}
+	fn notify(&mut self, success: bool) {
+		for waiter in self.waiters.drain(..) {
+			let _ = waiter.send(success);
+		}
+	}
+}
+
+// Files of a site waiting to be downloaded by its workers
+#[derive(Default)]
+pub struct DownloadQueue {
+	tasks: HashMap<String, FileRequest>,
+	paused: bool,
+}
+
+impl DownloadQueue {
+	pub fn new() -> DownloadQueue {
+		DownloadQueue::default()
+	}
+	// Queue a file, raising its priority if it was queued already,
+	// returns false if it was queued already
+	pub fn add(&mut self, mut request: FileRequest) -> bool {
+		if let Some(task) = self.tasks.get_mut(&request.inner_path) {
+			if request.file.is_some() {
+				task.file = request.file;
+			}
+			if request.priority < task.priority {
+				task.priority = request.priority;
+			}
+			// Asking for a file again gives it another chance
+			if task.status == Status::Failed {
+				task.status = Status::Waiting;
+				task.tries = 0;
+				task.tried_peers.clear();
+			}
+			return false;
+		}
+		if self.paused {
+			request.status = Status::Paused;
+		}
+		self.tasks.insert(request.inner_path.clone(), request);
+		true
+	}
+	pub fn get(&self, inner_path: &str) -> Option<&FileRequest> {
+		self.tasks.get(inner_path)
+	}
+	// Receiver notified with the result of a queued file, None if it isn't queued
+	pub fn wait(&mut self, inner_path: &str) -> Option<oneshot::Receiver<bool>> {
+		let task = self.tasks.get_mut(inner_path)?;
+		let (sender, receiver) = oneshot::channel();
+		task.waiters.push(sender);
+		Some(receiver)
+	}
+	// Mark the most urgent file that can be tried as downloading, returns its inner path
+	pub fn start_next(&mut self) -> Option<String> {
+		if self.paused {
+			return None;
+		}
+		let now = Utc::now();
+		let inner_path = self
+			.tasks
+			.values()
+			.filter(|task| task.can_start(now))
+			.min_by(|a, b| {
+				(a.priority, a.tries, &a.inner_path).cmp(&(b.priority, b.tries, &b.inner_path))
+			})?
+			.inner_path
+			.clone();
+		let task = self.tasks.get_mut(&inner_path)?;
+		task.status = Status::Downloading;
+		task.last_try = Some(now);
+		Some(inner_path)
+	}
+	// Put a file back without counting it as a try, when there's nobody to ask
+	pub fn requeue(&mut self, inner_path: &str) {
+		let paused = self.paused;
+		if let Some(task) = self.tasks.get_mut(inner_path) {
+			task.status = if paused {
+				Status::Paused
+			} else {
+				Status::Waiting
+			};
+			task.last_try = None;
+		}
+	}
+	// Forget a file that's no longer needed, the ones waiting for it are told it failed
+	pub fn remove(&mut self, inner_path: &str) {
+		if let Some(mut task) = self.tasks.remove(inner_path) {
+			task.notify(false);
+		}
+	}
+	pub fn done(&mut self, inner_path: &str) {
+		if let Some(mut task) = self.tasks.remove(inner_path) {
+			task.notify(true);
+		}
+	}
+	// Count a failed try, returns true once the file is given up on
+	pub fn failed(&mut self, inner_path: &str, peer: Option<&str>) -> bool {
+		let paused = self.paused;
+		let task = match self.tasks.get_mut(inner_path) {
+			Some(task) => task,
+			None => return false,
+		};
+		task.tries += 1;
+		if let Some(peer) = peer {
+			task.tried_peers.push(String::from(peer));
+		}
+		if task.tries >= MAX_TRIES {
+			task.status = Status::Failed;
+			task.notify(false);
+			return true;
+		}
+		task.status = if paused {
+			Status::Paused
+		} else {
+			Status::Waiting
+		};
+		false
+	}
+	// Let a file be tried with all peers again
+	pub fn clear_tried_peers(&mut self, inner_path: &str) {
+		if let Some(task) = self.tasks.get_mut(inner_path) {
+			task.tried_peers.clear();
+		}
+	}
+	// Stop starting new downloads, the ones in progress are finished
+	pub fn pause(&mut self) {
+		self.paused = true;
+		for task in self.tasks.values_mut() {
+			if task.status == Status::Waiting {
+				task.status = Status::Paused;
+			}
+		}
+	}
+	pub fn resume(&mut self) {
+		self.paused = false;
+		for task in self.tasks.values_mut() {
+			if task.status == Status::Paused {
+				task.status = Status::Waiting;
+			}
+		}
+	}
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+	// Number of files still to be downloaded, not counting the ones given up on
+	pub fn len(&self) -> usize {
+		self
+			.tasks
+			.values()
+			.filter(|task| task.status != Status::Failed)
+			.count()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+	// Bytes still to be downloaded, as far as the sizes are known
+	pub fn size(&self) -> usize {
+		self
+			.tasks
+			.values()
+			.filter(|task| task.status != Status::Failed)
+			.filter_map(|task| task.file.as_ref())
+			.map(|file| file.size)
+			.sum()
+	}
+	// Bytes being downloaded right now, as far as the sizes are known
+	pub fn downloading_size(&self) -> usize {
+		self
+			.tasks
+			.values()
+			.filter(|task| task.status == Status::Downloading)
+			.filter_map(|task| task.file.as_ref())
+			.map(|file| file.size)
+			.sum()
+	}
+	pub fn downloading(&self) -> usize {
+		self
+			.tasks
+			.values()
+			.filter(|task| task.status == Status::Downloading)
+			.count()
+	}
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::*;
+
+	fn queue_path(queue: &mut DownloadQueue, inner_path: &str, optional: bool) {
+		let priority = Priority::from_path(Path::new(inner_path), optional).unwrap();
+		queue.add(FileRequest::new(inner_path, priority, None));
+	}
+
+	#[test]
+	fn test_priority() {
+		let priority = |path: &str, optional: bool| Priority::from_path(Path::new(path), optional);
+		assert_eq!(priority("content.json", false), Ok(Priority::Content(0)));
+		assert!(priority("data/users/content.json", false) > Ok(Priority::Content(0)));
+		assert_eq!(priority("js/all.js", false), Ok(Priority::Site));
+		assert_eq!(priority("img/logo.png", false), Ok(Priority::Required));
+		assert_eq!(priority("video.mp4", true), Ok(Priority::Optional));
+		assert!(Priority::Site < Priority::Database);
+		assert!(Priority::Required < Priority::Optional);
+	}
+
+	#[test]
+	fn test_download_order() {
+		let mut queue = DownloadQueue::new();
+		queue_path(&mut queue, "video.mp4", true);
+		queue_path(&mut queue, "img/logo.png", false);
+		queue_path(&mut queue, "dbschema.json", false);
+		queue_path(&mut queue, "index.html", false);
+		queue_path(&mut queue, "data/users/content.json", false);
+		let mut order = vec![];
+		while let Some(inner_path) = queue.start_next() {
+			order.push(inner_path);
+		}
+		assert_eq!(
+			order,
+			vec![
+				"data/users/content.json",
+				"index.html",
+				"dbschema.json",
+				"img/logo.png",
+				"video.mp4"
+			]
+		);
+		assert_eq!(queue.downloading(), 5);
+	}
+
+	#[test]
+	fn test_failed_tries() {
+		let mut queue = DownloadQueue::new();
+		queue_path(&mut queue, "index.html", false);
+		let mut result = queue.wait("index.html").unwrap();
+		for _ in 1..MAX_TRIES {
+			queue.start_next();
+			assert!(!queue.failed("index.html", Some("1.2.3.4:15441")));
+			// Backing off before the next try
+			assert_eq!(queue.start_next(), None);
+			queue.tasks.get_mut("index.html").unwrap().last_try = None;
+		}
+		assert!(queue.failed("index.html", None));
+		assert!(queue.is_empty());
+		assert_eq!(result.try_recv(), Ok(Some(false)));
+		// Asking again resets the tries
+		queue_path(&mut queue, "index.html", false);
+		assert_eq!(queue.start_next(), Some(String::from("index.html")));
+	}
+
+	#[test]
+	fn test_size() {
+		let mut queue = DownloadQueue::new();
+		let file = |size| ContentFile {
+			sha512: String::new(),
+			size,
+		};
+		queue.add(FileRequest::new(
+			"index.html",
+			Priority::Site,
+			Some(file(100)),
+		));
+		queue.add(FileRequest::new(
+			"img/logo.png",
+			Priority::Required,
+			Some(file(50)),
+		));
+		queue.add(FileRequest::new("content.json", Priority::Content(0), None));
+		assert_eq!(queue.size(), 150);
+		queue.start_next();
+		queue.start_next();
+		assert_eq!(queue.downloading_size(), 100);
+	}
+
+	#[test]
+	fn test_pause() {
+		let mut queue = DownloadQueue::new();
+		queue_path(&mut queue, "index.html", false);
+		queue.pause();
+		queue_path(&mut queue, "js/all.js", false);
+		assert_eq!(queue.start_next(), None);
+		queue.resume();
+		assert_eq!(queue.start_next(), Some(String::from("index.html")));
+		assert_eq!(queue.start_next(), Some(String::from("js/all.js")));
+	}
 }
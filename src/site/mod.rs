@@ -1,177 +1,1730 @@
 pub mod address;
+pub mod content_rules;
+pub mod diff;
+pub mod file_request;
 mod site_info;
 pub mod site_manager;
 pub mod site_storage;
 
 use crate::error::Error;
-use crate::peer::Peer;
+use crate::optional_files::hashfield::{hash_id, Hashfield};
+use crate::peer::{
+	BadFile, DownloadedFile, FileDownload, FindHashIds, GetHashfield, ListModified, Peer, PeerStats,
+	PeerStatsRequest, Pex, SetHashfield, Update,
+};
 use crate::server::websocket::ZeruWebsocket;
+use crate::util::{is_valid_inner_path, HashWriter};
 use actix;
 use actix::prelude::*;
 use address::Address;
+use chrono::{DateTime, Duration, Utc};
+use content_rules::{matches_start, Rules};
+use diff::Diffs;
+use file_request::{DownloadQueue, FileRequest, Priority, Status, MAX_TRIES};
 use futures::executor::block_on;
+use futures::future::{join_all, FutureExt, LocalBoxFuture};
 use log::*;
 use serde_derive::{Deserialize, Serialize};
 use site_info::{SiteInfo, SiteSettings};
-use std::collections::HashMap;
-use std::io::Write;
-use std::path::PathBuf;
-use zerucontent::Content;
+use site_manager::{AnnounceSite, SiteManager};
+use site_storage::{SiteStorage, VerifyReport};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use zerucontent::{Content, File as ContentFile, UserContents};
+
+// Number of peers requested and shared in a pex request
+const PEX_NEED: usize = 5;
+// Number of peers we exchange hashfields with at once
+const HASHFIELD_PEERS: usize = 5;
+// Maximum number of peers returned per hash id in a findHashIds response
+const FIND_HASH_IDS_LIMIT: usize = 20;
+// Number of peers asked for modified content.json files in an update
+const LIST_MODIFIED_PEERS: usize = 3;
+// Seconds subtracted from our last modification when asking for modified files,
+// to make up for clock differences and files we've missed
+const LIST_MODIFIED_MARGIN: f64 = 60.0 * 60.0 * 24.0;
+// Number of peers an update pushed to us is passed on to
+const UPDATE_RELAY_PEERS: usize = 3;
+// Default number of peers a site is published to
+pub const PUBLISH_LIMIT: usize = 5;
+// Default size limit of a site in MB
+pub const SIZE_LIMIT: usize = 10;
+// Default maximum size of a content.json file in MB
+pub const FILE_SIZE_LIMIT: usize = 10;
+// Size limits in MB the user is asked to raise the limit to, in order
+const SIZE_LIMITS: &[usize] = &[
+	10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000, 20000, 50000, 100000,
+];
+// Directory in the data directory downloads are written to until they're verified
+const TEMP_DIR: &str = ".tmp";
+// Number of bad files after which a peer is dropped from the site
+const MAX_PEER_BAD_FILES: usize = 3;
+// Default number of peers a site connects to
+pub const CONNECTED_LIMIT: usize = 8;
+// Number of files downloaded at the same time
+const MAX_WORKERS: usize = 5;
+// How often the download queue is checked for files that can be (re)tried
+const WORKER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+// How often bad files are checked for ones to retry
+const BAD_FILES_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+// Seconds to wait before retrying a bad file, doubled for every round of tries
+const BAD_FILE_RETRY_DELAY: i64 = 60;
+const BAD_FILE_MAX_RETRY_DELAY: i64 = 60 * 60;
+// How often a site is updated when it was visited in the last hour,
+// in the last day, or less recently
+const UPDATE_INTERVAL_ACTIVE: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+const UPDATE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(20 * 60);
+const UPDATE_INTERVAL_IDLE: std::time::Duration = std::time::Duration::from_secs(2 * 60 * 60);
+
+// Peer known to serve this site
+struct SitePeer {
+	addr: Addr<Peer>,
+	// Network address shared with other peers over pex
+	address: String,
+	// Optional files the peer has, None until requested or sent by the peer
+	hashfield: Option<Hashfield>,
+	// Stats as of our last request to the peer
+	stats: PeerStats,
+}
+
+impl SitePeer {
+	fn has_hash_id(&self, hash_id: u16) -> bool {
+		match &self.hashfield {
+			Some(hashfield) => hashfield.contains(hash_id),
+			None => false,
+		}
+	}
+}
 
 pub struct Site {
 	address: Address,
-	peers: HashMap<String, Addr<Peer>>,
+	peers: HashMap<String, SitePeer>,
 	settings: SiteSettings,
 	content: Option<Content>,
-	// TODO: queued files should be more than just a string,
-	// they should have a priority, try-count, last-try timestamp,
-	queued_files: Vec<String>,
+	// content.json files of includes and user content by inner path
+	contents: BTreeMap<String, Content>,
+	// Optional files we have
+	hashfield: Hashfield,
+	// Set when our hashfield has changed since it was last sent to peers
+	hashfield_changed: bool,
+	// Hash ids of optional files we've asked peers about since the last update
+	hash_id_lookups: HashSet<u16>,
+	// Files waiting to be downloaded by the workers
+	queue: DownloadQueue,
 	data_path: PathBuf,
 	listeners: Vec<Addr<ZeruWebsocket>>,
+	// Maximum number of peers we make requests to
+	connected_limit: usize,
+	// Number of peers our content is published to
+	publish_limit: usize,
+	// Default size limit in MB, unless the user approved another one
+	size_limit: usize,
+	// Maximum size of a content.json file in MB
+	file_size_limit: usize,
+	// Last time a websocket joined the site
+	visited: Option<DateTime<Utc>>,
+	// Last time the updater ran
+	updated: Option<DateTime<Utc>>,
+	// Next run of the updater
+	updater_handle: Option<SpawnHandle>,
+	// Announces the site to the trackers
+	site_manager: Option<Addr<SiteManager>>,
 }
 
 impl Site {
 	pub fn new(listeners: Vec<Addr<ZeruWebsocket>>, address: Address, data_path: PathBuf) -> Site {
-		let mut settings = SiteSettings::default();
-		settings.serving = true;
 		Site {
 			address,
 			peers: HashMap::new(),
-			settings,
+			settings: SiteSettings::new(),
 			content: None,
-			queued_files: Vec::new(),
+			contents: BTreeMap::new(),
+			hashfield: Hashfield::new(),
+			hashfield_changed: false,
+			hash_id_lookups: HashSet::new(),
+			queue: DownloadQueue::new(),
 			listeners,
 			data_path,
+			connected_limit: CONNECTED_LIMIT,
+			publish_limit: PUBLISH_LIMIT,
+			size_limit: SIZE_LIMIT,
+			file_size_limit: FILE_SIZE_LIMIT,
+			visited: None,
+			updated: None,
+			updater_handle: None,
+			site_manager: None,
 		}
 	}
 	pub fn load_settings() {}
 	pub fn save_settings() {}
-	pub fn is_serving() {}
+	// Paused sites keep their files, but don't download, update or serve them to peers
+	pub fn is_serving(&self) -> bool {
+		self.settings.serving
+	}
 	pub fn get_settings_cache() {}
-	pub fn get_size_limit() {}
-	pub fn get_next_size_limit() {}
-	// Download content files
-	pub fn download_content(&mut self, inner_path: &str) -> Result<(), Error> {
-		let buf = self.download_file(inner_path)?;
-		let content = match Content::from_buf(buf) {
-			Ok(c) => c,
-			Err(_) => return Err(Error::MissingError),
+	// Size limit in MB
+	pub fn get_size_limit(&self) -> usize {
+		match self.settings.size_limit {
+			0 => self.size_limit,
+			size_limit => size_limit,
+		}
+	}
+	// Size limit in MB to ask the user for, big enough for the whole site with some room to grow
+	pub fn get_next_size_limit(&self) -> usize {
+		let size = self.get_size_needed() as f64 * 1.2;
+		SIZE_LIMITS
+			.iter()
+			.find(|limit| size < (**limit * 1024 * 1024) as f64)
+			.cloned()
+			.unwrap_or(999_999)
+	}
+	// Bytes the site takes up once the queued files are downloaded
+	pub fn get_size_needed(&self) -> usize {
+		self.settings.size + self.queue.size()
+	}
+	// Whether a file of `size` bytes can be downloaded along with the ones in progress
+	fn fits_size_limit(&self, size: usize) -> bool {
+		let limit = self.get_size_limit() * 1024 * 1024;
+		self.settings.size + self.queue.downloading_size() + size <= limit
+	}
+	fn site_path(&self) -> PathBuf {
+		self.data_path.join(self.address.to_string())
+	}
+	fn get_content(&self, inner_path: &str) -> Option<&Content> {
+		if inner_path == "content.json" {
+			return self.content.as_ref();
+		}
+		self.contents.get(inner_path)
+	}
+	fn content_modified(&self, inner_path: &str) -> Option<f64> {
+		self.get_content(inner_path).map(|c| c.modified)
+	}
+	fn store_content(&mut self, inner_path: &str, content: Content) {
+		if content.modified > self.settings.modified {
+			self.settings.modified = content.modified;
+		}
+		if inner_path == "content.json" {
+			self.content = Some(content);
+		} else {
+			self.contents.insert(String::from(inner_path), content);
+		}
+	}
+	// Rules for a content.json file from the closest parent content.json that
+	// has includes or user contents, the way the reference client looks them up
+	fn get_rules(&self, inner_path: &str, content: &Content) -> Option<Rules> {
+		let mut dirs: Vec<&str> = inner_path.split('/').collect();
+		dirs.pop();
+		let mut relative_path = String::from("content.json");
+		while let Some(dir) = dirs.pop() {
+			relative_path = format!("{}/{}", dir, relative_path);
+			let parent_path = if dirs.is_empty() {
+				String::from("content.json")
+			} else {
+				format!("{}/content.json", dirs.join("/"))
+			};
+			let parent = match self.get_content(&parent_path) {
+				Some(parent) => parent,
+				None => continue,
+			};
+			if !parent.includes.is_empty() {
+				return parent.includes.get(&relative_path).map(Rules::from_include);
+			}
+			if parent.user_contents != UserContents::default() {
+				return Rules::from_user_contents(&parent.user_contents, inner_path, content);
+			}
+		}
+		None
+	}
+	// Whether a user content.json file, as of its modified time, was archived by the
+	// site owner in the user_contents of the content.json in the parent directory
+	fn is_archived(&self, inner_path: &str, modified: f64) -> bool {
+		let mut dirs: Vec<&str> = inner_path.split('/').collect();
+		dirs.pop();
+		let user_dir = match dirs.pop() {
+			Some(dir) => dir,
+			None => return false,
 		};
-		// TODO: verify content
-		self.content = Some(content);
-		Ok(())
+		let parent_path = if dirs.is_empty() {
+			String::from("content.json")
+		} else {
+			format!("{}/content.json", dirs.join("/"))
+		};
+		match self.get_content(&parent_path) {
+			Some(parent) => parent.user_contents.is_archived(user_dir, modified),
+			None => false,
+		}
 	}
-	pub fn get_reachable_bad_files() {}
-	pub fn retry_bad_files() {}
-	pub fn check_bad_files() {}
-	// Initial download of site
-	pub fn download_site(&mut self) -> Result<(), Error> {
-		if self.content.is_none() {
-			self.download_content("content.json")?;
+	// Remove the user content archived by a content.json file,
+	// returns the number of user content.json files removed
+	fn remove_archived(&mut self, inner_path: &str) -> usize {
+		match self.get_content(inner_path) {
+			Some(content)
+				if !content.user_contents.archived.is_empty()
+					|| content.user_contents.archived_before > 0 => {}
+			_ => return 0,
+		}
+		let dir = content_dir(inner_path);
+		let archived: Vec<String> = self
+			.contents
+			.iter()
+			.filter(|(path, _)| path.starts_with(dir) && path.as_str() != inner_path)
+			.filter(|(path, content)| self.is_archived(path, content.modified))
+			.map(|(path, _)| path.clone())
+			.collect();
+		for path in archived.iter() {
+			self.remove_content(path);
+		}
+		if !archived.is_empty() {
+			info!(
+				"Removed {} archived user contents of {}",
+				archived.len(),
+				self.address.get_address_short()
+			);
+		}
+		archived.len()
+	}
+	// Forget a content.json file and remove it from disk along with the files it lists
+	fn remove_content(&mut self, inner_path: &str) {
+		let content = match self.contents.remove(inner_path) {
+			Some(content) => content,
+			None => return,
+		};
+		let dir = content_dir(inner_path);
+		let site_path = self.site_path();
+		let paths = content
+			.files
+			.keys()
+			.chain(content.files_optional.keys())
+			.map(|path| format!("{}{}", dir, path))
+			.chain(std::iter::once(String::from(inner_path)))
+			.filter(|path| is_valid_inner_path(path));
+		for path in paths {
+			self.queue.remove(&path);
+			self.settings.cache.bad_files.remove(&path);
+			let file_path = site_path.join(&path);
+			let size = match file_path.metadata() {
+				Ok(m) => m.len() as usize,
+				Err(_) => continue,
+			};
+			match std::fs::remove_file(&file_path) {
+				Ok(()) => self.settings.size = self.settings.size.saturating_sub(size),
+				Err(err) => error!("Could not remove {:?}: {:?}", file_path, err),
+			}
+		}
+		// Only removed once nothing else is left in it
+		let _ = std::fs::remove_dir(site_path.join(dir));
+	}
+	// Verify a content.json file of `size` bytes, the root one has to be signed by the site,
+	// others have to follow the rules of the content.json including them
+	fn verify_content(&self, inner_path: &str, content: &Content, size: usize) -> bool {
+		if inner_path != "content.json" {
+			if self.is_archived(inner_path, content.modified) {
+				error!(
+					"zero://{}/{} is archived",
+					self.address.get_address_short(),
+					inner_path
+				);
+				return false;
+			}
+			let rules = match self.get_rules(inner_path, content) {
+				Some(rules) => rules,
+				None => {
+					error!(
+						"No rules for zero://{}/{}",
+						self.address.get_address_short(),
+						inner_path
+					);
+					return false;
+				}
+			};
+			if let Err(err) = rules.verify(&self.address.to_string(), content, size) {
+				error!(
+					"zero://{}/{} is not valid: {:?}",
+					self.address.get_address_short(),
+					inner_path,
+					err
+				);
+				return false;
+			}
+			return true;
 		}
-		// TODO: how to best avoid this clone?
-		// I'd say instead of "needing" files here, adding them to a queue and
-		// downloading them later instead would solve the problem adequately.
-		let content = self.content.as_mut().unwrap().clone();
 		if !content.verify(self.address.to_string()) {
 			error!(
 				"Content signature {:?} is not valid for address {}!",
 				content.signs,
 				self.address.to_string()
 			);
+			return false;
+		}
+		true
+	}
+	// Download content files, returns false if we already had a version at least as new
+	pub fn download_content(&mut self, inner_path: &str) -> Result<bool, Error> {
+		let buf = self.download_file(inner_path, None)?;
+		self.apply_content(inner_path, buf)
+	}
+	// Verify a received content.json file and store it if it is newer than ours
+	fn apply_content(&mut self, inner_path: &str, buf: serde_bytes::ByteBuf) -> Result<bool, Error> {
+		if buf.len() > self.file_size_limit * 1024 * 1024 {
+			error!(
+				"zero://{}/{} is larger than {}MB",
+				self.address.get_address_short(),
+				inner_path,
+				self.file_size_limit
+			);
+			return Err(Error::SizeLimit);
+		}
+		let content = match Content::from_buf(buf.clone()) {
+			Ok(c) => c,
+			Err(_) => return Err(Error::MissingError),
+		};
+		if let Some(modified) = self.content_modified(inner_path) {
+			if content.modified <= modified {
+				trace!(
+					"Received zero://{}/{} is not newer than ours",
+					self.address.get_address_short(),
+					inner_path
+				);
+				return Ok(false);
+			}
+		}
+		if !self.verify_content(inner_path, &content, buf.len()) {
 			return Err(Error::MissingError);
 		}
-		for (key, value) in content.files.iter() {
-			self.need_file(key); // TODO: handle result
+		self.write_site_file(inner_path, &buf)?;
+		self.store_content(inner_path, content);
+		self.remove_archived(inner_path);
+		Ok(true)
+	}
+	// Load the content.json files we already have from disk,
+	// returns the number of content.json files loaded
+	fn load_contents(&mut self) -> usize {
+		let mut paths = vec![];
+		find_content_files(&self.site_path(), "", &mut paths);
+		let mut loaded = 0;
+		for inner_path in paths {
+			let content = std::fs::read(self.site_path().join(&inner_path))
+				.ok()
+				.and_then(|buf| Content::from_buf(serde_bytes::ByteBuf::from(buf)).ok());
+			match content {
+				Some(content) => {
+					self.store_content(&inner_path, content);
+					loaded += 1;
+				}
+				None => error!("Could not load zero://{}/{}", self.address, inner_path),
+			}
+		}
+		// Archived while we weren't looking
+		let paths: Vec<String> = self.contents.keys().cloned().collect();
+		self.remove_archived("content.json");
+		for inner_path in paths {
+			self.remove_archived(&inner_path);
+		}
+		loaded
+	}
+	// Bad files still listed in our content.json files, with their number of failed tries
+	pub fn get_reachable_bad_files(&self) -> Vec<(String, usize)> {
+		self
+			.settings
+			.cache
+			.bad_files
+			.iter()
+			.filter(|(inner_path, _)| {
+				inner_path.ends_with("content.json") || self.need_file_info(inner_path).is_some()
+			})
+			.map(|(inner_path, tries)| (inner_path.clone(), *tries))
+			.collect()
+	}
+	// Queue the bad files that are no longer being tried and have waited out
+	// their backoff, returns the number of files queued
+	pub fn retry_bad_files(&mut self) -> usize {
+		let now = Utc::now();
+		let mut queued = 0;
+		for (inner_path, tries) in self.get_reachable_bad_files() {
+			let last_try = match self.queue.get(&inner_path) {
+				Some(task) if task.status != Status::Failed => continue,
+				Some(task) => task.last_try,
+				None => None,
+			};
+			if let Some(last_try) = last_try {
+				if now - last_try < bad_file_retry_delay(tries) {
+					continue;
+				}
+			}
+			let file = self.need_file_info(&inner_path);
+			let optional = self.optional_hash_id(&inner_path).is_some();
+			let priority =
+				Priority::from_path(Path::new(&inner_path), optional).unwrap_or(Priority::Required);
+			self
+				.queue
+				.add(FileRequest::new(&inner_path, priority, file));
+			queued += 1;
+		}
+		if queued > 0 {
+			trace!(
+				"Retrying {} bad files of {}",
+				queued,
+				self.address.get_address_short()
+			);
+		}
+		queued
+	}
+	// Forget bad files that are no longer listed or that we have by now,
+	// returns the number of bad files left
+	pub fn check_bad_files(&mut self) -> usize {
+		let reachable: Vec<String> = self
+			.get_reachable_bad_files()
+			.into_iter()
+			.map(|(inner_path, _)| inner_path)
+			.collect();
+		let site_path = self.site_path();
+		let mut bad_files = std::mem::take(&mut self.settings.cache.bad_files);
+		bad_files.retain(|inner_path, _| {
+			if !reachable.contains(inner_path) {
+				return false;
+			}
+			match self.need_file_info(inner_path) {
+				Some(file) => file_hash(&site_path.join(inner_path)).as_ref() != Some(&file.sha512),
+				None => self.get_content(inner_path).is_none(),
+			}
+		});
+		self.settings.cache.bad_files = bad_files;
+		self.settings.cache.bad_files.len()
+	}
+	// Initial download of site
+	pub fn download_site(&mut self, ctx: &mut Context<Self>) -> Result<(), Error> {
+		self.settings.size = dir_size(&self.site_path());
+		if !self.is_serving() {
+			// Only the files we already have are used
+			if self.content.is_none() {
+				self.load_contents();
+			}
+			return match self.content {
+				Some(_) => Ok(()),
+				None => Err(Error::MissingError),
+			};
+		}
+		if self.peers.len() < PEX_NEED {
+			self.announce_pex(ctx);
+		}
+		if self.content.is_none() && self.load_contents() > 0 && self.content.is_some() {
+			// Pick up what changed while we weren't looking
+			let update = self.update();
+			ctx.spawn(update.map(|_, _, _| ()));
+		}
+		if self.content.is_none() {
+			self.download_content("content.json")?;
+		}
+		// Also set for sites whose files we had before they were in sites.json
+		if self.settings.downloaded == 0 {
+			self.settings.downloaded = Utc::now().timestamp();
+		}
+		self.load_hashfield();
+		let queued = self.queue_content_files("content.json");
+		trace!(
+			"Queued {} files of {}",
+			queued,
+			self.address.get_address_short()
+		);
+		if self.check_bad_files() > 0 {
+			self.retry_bad_files();
 		}
 		Ok(())
 	}
-	// Download file
-	pub fn download_file(&mut self, inner_path: &str) -> Result<serde_bytes::ByteBuf, Error> {
-		if self.peers.len() == 0 {
+	// Queue the files listed in a content.json file that we don't have yet,
+	// along with the content.json files it includes, returns the number queued
+	fn queue_content_files(&mut self, inner_path: &str) -> usize {
+		let content = match self.get_content(inner_path) {
+			Some(content) => content,
+			None => return 0,
+		};
+		let dir = content_dir(inner_path);
+		let site_path = self.site_path();
+		let requests: Vec<FileRequest> = content
+			.files
+			.iter()
+			.map(|(path, file)| (format!("{}{}", dir, path), Some(file.clone())))
+			.chain(
+				content
+					.includes
+					.keys()
+					.map(|path| (format!("{}{}", dir, path), None)),
+			)
+			.filter(|(path, _)| is_valid_inner_path(path) && !site_path.join(path).is_file())
+			.filter_map(|(path, file)| {
+				let priority = Priority::from_path(Path::new(&path), false).ok()?;
+				Some(FileRequest::new(&path, priority, file))
+			})
+			.collect();
+		let mut queued = 0;
+		for request in requests {
+			if self.queue.add(request) {
+				queued += 1;
+			}
+		}
+		queued
+	}
+	// Download file, verifying it if its size and hash are known.
+	// Peers are tried best first, the ones serving bad files get demoted
+	pub fn download_file(
+		&mut self,
+		inner_path: &str,
+		expected: Option<&ContentFile>,
+	) -> Result<serde_bytes::ByteBuf, Error> {
+		self.cleanup_peers();
+		if self.peers.is_empty() {
 			trace!("No peers for {}", self.address.to_string());
 			return Err(Error::MissingError);
 		}
-		// TODO: Do some smart peer management here instead of this ... whatever it is
-		for (key, peer) in self.peers.iter() {
+		let hash_id = self.optional_hash_id(inner_path);
+		let size = expected.map_or(0, |file| file.size);
+		for (key, addr) in self.connectable_addrs(hash_id) {
 			let req = crate::peer::FileGetRequest {
 				inner_path: inner_path.to_string(),
 				site_address: self.address.clone(),
+				file_size: size,
+				progress: None,
 			};
-			if let Ok(Ok(buf)) = block_on(peer.send(req)) {
-				return Ok(buf);
+			let (result, stats) = block_on(async {
+				let result = addr.send(req).await;
+				(result, addr.send(PeerStatsRequest {}).await)
+			});
+			if let Ok(Ok(stats)) = stats {
+				self.set_stats(&key, stats);
 			}
+			let buf = match result {
+				Ok(Ok(buf)) => buf,
+				_ => continue,
+			};
+			if let Some(file) = expected {
+				if buf.len() != file.size || hash(&buf) != file.sha512 {
+					error!(
+						"zero://{}/{} from {} does not match its hash",
+						self.address.get_address_short(),
+						inner_path,
+						key
+					);
+					self.bad_file(&key, inner_path);
+					continue;
+				}
+			}
+			return Ok(buf);
 		}
 		return Err(Error::MissingError);
 	}
-	pub fn updater() {}
-	pub fn check_modifications() {}
-	pub fn update() {}
+	// Peer keys ordered by preference, peers known to have
+	// the optional file come first, then the best scoring ones
+	fn ranked_peers(&self, hash_id: Option<u16>) -> Vec<String> {
+		let has_file = |peer: &SitePeer| hash_id.map_or(false, |id| peer.has_hash_id(id));
+		let mut keys: Vec<&String> = self.peers.keys().collect();
+		keys.sort_by(|a, b| {
+			let (a, b) = (&self.peers[*a], &self.peers[*b]);
+			has_file(b).cmp(&has_file(a)).then(
+				b.stats
+					.score()
+					.partial_cmp(&a.stats.score())
+					.unwrap_or(Ordering::Equal),
+			)
+		});
+		keys.into_iter().cloned().collect()
+	}
+	// Keep the stats of a peer we received after making a request to it
+	fn set_stats(&mut self, key: &str, stats: PeerStats) {
+		if let Some(peer) = self.peers.get_mut(key) {
+			peer.stats = stats;
+		}
+	}
+	// Demote a peer that served a bad file, dropping it if it keeps doing so
+	fn bad_file(&mut self, key: &str, inner_path: &str) {
+		let bad_files = match self.peers.get_mut(key) {
+			Some(peer) => {
+				peer.addr.do_send(BadFile {
+					site_address: self.address.clone(),
+					inner_path: String::from(inner_path),
+				});
+				// Counted by the peer as well, its stats come with the next request
+				peer.stats.bad_files += 1;
+				peer.stats.bad_files
+			}
+			None => return,
+		};
+		if bad_files >= MAX_PEER_BAD_FILES {
+			info!(
+				"Dropping {} as peer for {} after {} bad files",
+				key,
+				self.address.get_address_short(),
+				bad_files
+			);
+			self.peers.remove(key);
+		}
+	}
+	// Announce the site, download what changed and schedule the next run,
+	// the site has to be loaded from disk or downloaded already
+	pub fn updater(&mut self, ctx: &mut Context<Self>) {
+		if self.is_serving() {
+			if self.content.is_none() {
+				self.load_contents();
+			}
+			if self.content.is_some() {
+				self.updated = Some(Utc::now());
+				// Peers may have gotten optional files we couldn't find before
+				self.hash_id_lookups.clear();
+				self.announce(ctx);
+				let update = self.update();
+				ctx.spawn(update.map(|_, _, _| ()));
+				self.run_workers(ctx);
+			}
+		}
+		self.schedule_update(ctx, self.update_interval());
+	}
+	// Sites are updated more often the more recently they were visited
+	fn update_interval(&self) -> std::time::Duration {
+		let idle = self.visited.map(|visited| Utc::now() - visited);
+		match idle {
+			Some(idle) if idle < Duration::hours(1) => UPDATE_INTERVAL_ACTIVE,
+			Some(idle) if idle < Duration::days(1) => UPDATE_INTERVAL,
+			_ => UPDATE_INTERVAL_IDLE,
+		}
+	}
+	// Run the updater after `delay`, replacing the run scheduled before
+	fn schedule_update(&mut self, ctx: &mut Context<Self>, delay: std::time::Duration) {
+		if let Some(handle) = self.updater_handle.take() {
+			ctx.cancel_future(handle);
+		}
+		self.updater_handle = Some(ctx.run_later(delay, |act, ctx| act.updater(ctx)));
+	}
+	// Keep track of the site being visited, updating it right away
+	// if it hasn't been for a while
+	fn visit(&mut self, ctx: &mut Context<Self>) {
+		self.visited = Some(Utc::now());
+		let stale = match self.updated {
+			Some(updated) => {
+				Utc::now() - updated > Duration::seconds(UPDATE_INTERVAL_ACTIVE.as_secs() as i64)
+			}
+			None => true,
+		};
+		if stale {
+			self.schedule_update(ctx, std::time::Duration::from_secs(0));
+		}
+	}
+	// Ask peers which content.json files have been modified since the given timestamp,
+	// resolves to the newest modification time reported for each of them
+	fn check_modifications(&self, since: f64) -> LocalBoxFuture<'static, BTreeMap<String, f64>> {
+		let peers = self.connectable_addrs(None);
+		let site_address = self.address.clone();
+		async move {
+			let mut modified_files: BTreeMap<String, f64> = BTreeMap::new();
+			let mut responded = 0;
+			for (key, addr) in peers {
+				let msg = ListModified {
+					site_address: site_address.clone(),
+					since,
+				};
+				match addr.send(msg).await {
+					Ok(Ok(res)) => {
+						responded += 1;
+						for (inner_path, modified) in res {
+							if !is_valid_inner_path(&inner_path) || !inner_path.ends_with("content.json") {
+								continue;
+							}
+							let newest = modified_files.entry(inner_path).or_insert(modified);
+							if modified > *newest {
+								*newest = modified;
+							}
+						}
+					}
+					_ => trace!("listModified with {} failed", key),
+				}
+				if responded >= LIST_MODIFIED_PEERS {
+					break;
+				}
+			}
+			modified_files
+		}
+		.boxed_local()
+	}
+	// Queue the modified content.json files that are newer than ours for the workers,
+	// which also queue the files that changed in them, returns the number queued
+	fn queue_modified(&mut self, modified_files: BTreeMap<String, f64>) -> usize {
+		let mut queued = 0;
+		for (inner_path, modified) in modified_files {
+			// Archived user content is not downloaded again
+			if self.is_archived(&inner_path, modified) {
+				continue;
+			}
+			if let Some(ours) = self.content_modified(&inner_path) {
+				if modified <= ours {
+					continue;
+				}
+			}
+			let priority =
+				Priority::from_path(Path::new(&inner_path), false).unwrap_or(Priority::Required);
+			if self
+				.queue
+				.add(FileRequest::new(&inner_path, priority, None))
+			{
+				queued += 1;
+			}
+		}
+		queued
+	}
+	// Ask peers for the content.json files modified since our last update and download
+	// the newer ones, resolves to the number of content.json files queued
+	pub fn update(&mut self) -> ResponseActFuture<Self, usize> {
+		if !self.is_serving() {
+			trace!("Not updating paused site {}", self.address);
+			return Box::pin(fut::ready(0));
+		}
+		self.cleanup_peers();
+		let since = (self.settings.modified - LIST_MODIFIED_MARGIN).max(0.0);
+		let check = self.check_modifications(since).into_actor(self);
+		Box::pin(check.map(|modified_files, act, ctx| {
+			let queued = act.queue_modified(modified_files);
+			if queued > 0 {
+				info!(
+					"Queued {} modified content.json files of {}",
+					queued,
+					act.address.get_address_short()
+				);
+			}
+			act.run_workers(ctx);
+			queued
+		}))
+	}
+	// Store a content.json file pushed to us by a peer, queue its changed files
+	// and pass it on to a few of our peers, returns false if it was not newer
+	fn receive_update(
+		&mut self,
+		inner_path: &str,
+		body: serde_bytes::ByteBuf,
+		diffs: Diffs,
+		sender: Option<&str>,
+	) -> Result<bool, Error> {
+		if !is_valid_inner_path(inner_path) || !inner_path.ends_with("content.json") {
+			return Err(Error::MissingError);
+		}
+		let old_content = self.get_content(inner_path).cloned();
+		if !self.apply_content(inner_path, body.clone())? {
+			return Ok(false);
+		}
+		info!(
+			"Received update of zero://{}/{}",
+			self.address.get_address_short(),
+			inner_path
+		);
+		let patched = self.apply_diffs(inner_path, &diffs);
+		if patched > 0 {
+			trace!(
+				"Patched {}/{} files of zero://{}/{}",
+				patched,
+				diffs.len(),
+				self.address.get_address_short(),
+				inner_path
+			);
+		}
+		// Files that couldn't be patched are downloaded
+		self.queue_changed_files(inner_path, old_content);
+		self.relay_update(inner_path, body, diffs, sender);
+		Ok(true)
+	}
+	// Patch the files of a content.json file with the diffs sent along with it,
+	// returns the number of files patched to the version listed
+	fn apply_diffs(&mut self, inner_path: &str, diffs: &Diffs) -> usize {
+		let dir = content_dir(inner_path);
+		let site_path = self.site_path();
+		let mut patched = 0;
+		for (path, actions) in diffs.iter() {
+			let file_path = format!("{}{}", dir, path);
+			let file = match self.get_content(inner_path).and_then(|c| c.files.get(path)) {
+				Some(file) if is_valid_inner_path(&file_path) => file.clone(),
+				_ => continue,
+			};
+			let old = match std::fs::read(site_path.join(&file_path)) {
+				Ok(old) => old,
+				Err(_) => continue,
+			};
+			let new = match diff::patch(&old, actions) {
+				Ok(new) => new,
+				Err(err) => {
+					trace!(
+						"Could not patch zero://{}/{}: {:?}",
+						self.address,
+						file_path,
+						err
+					);
+					continue;
+				}
+			};
+			if new.len() != file.size || hash(&new) != file.sha512 {
+				trace!(
+					"Patched zero://{}/{} does not match its hash",
+					self.address.get_address_short(),
+					file_path
+				);
+				continue;
+			}
+			match self.write_site_file(&file_path, &new) {
+				Ok(()) => patched += 1,
+				Err(err) => error!("Error writing patched {}: {:?}", file_path, err),
+			}
+		}
+		patched
+	}
+	// Pass an update on to our best peers other than the one that sent it,
+	// without waiting for them, returns the number of peers it was sent to
+	fn relay_update(
+		&self,
+		inner_path: &str,
+		body: serde_bytes::ByteBuf,
+		diffs: Diffs,
+		sender: Option<&str>,
+	) -> usize {
+		let modified = self.content_modified(inner_path).unwrap_or(0.0);
+		let keys = self.get_connectable_peers(None);
+		let peers = keys
+			.iter()
+			.filter_map(|k| self.peers.get(k))
+			.filter(|peer| Some(peer.address.as_str()) != sender)
+			.take(UPDATE_RELAY_PEERS);
+		let mut sent = 0;
+		for peer in peers {
+			peer.addr.do_send(Update {
+				site_address: self.address.clone(),
+				inner_path: String::from(inner_path),
+				body: body.clone(),
+				modified,
+				diffs: diffs.clone(),
+			});
+			sent += 1;
+		}
+		sent
+	}
+	// Queue the files of a content.json file that are new or changed since
+	// `old_content`, optional files only if we have an older version of them
+	fn queue_changed_files(&mut self, inner_path: &str, old_content: Option<Content>) {
+		let content = match self.get_content(inner_path) {
+			Some(content) => content,
+			None => return,
+		};
+		let dir = content_dir(inner_path);
+		let site_path = self.site_path();
+		let old_files = old_content.as_ref().map(|content| &content.files);
+		let old_files_optional = old_content.as_ref().map(|content| &content.files_optional);
+		let mut changed: Vec<(String, ContentFile, Priority)> = content
+			.files
+			.iter()
+			.filter(|(path, file)| is_changed(old_files, path, file))
+			.filter_map(|(path, file)| {
+				let path = format!("{}{}", dir, path);
+				let priority = Priority::from_path(Path::new(&path), false).ok()?;
+				Some((path, file.clone(), priority))
+			})
+			.collect();
+		// Only optional files we have are kept up to date
+		changed.extend(
+			content
+				.files_optional
+				.iter()
+				.filter(|(path, file)| is_changed(old_files_optional, path, file))
+				.map(|(path, file)| {
+					(
+						format!("{}{}", dir, path),
+						file.clone(),
+						Priority::Background,
+					)
+				})
+				.filter(|(path, _, _)| site_path.join(path).is_file()),
+		);
+		for (path, file, priority) in changed {
+			let file_path = site_path.join(&path);
+			if file_path.is_file() && file_hash(&file_path).as_ref() == Some(&file.sha512) {
+				continue;
+			}
+			self
+				.queue
+				.add(FileRequest::new(&path, priority, Some(file)));
+		}
+	}
 	pub fn redownload_contents() {}
-	pub fn publisher() {}
-	pub fn publish() {}
-	pub fn clone() {}
+	// Push a content.json file we have to `publish_limit` peers, reporting every peer
+	// reached to `progress`, resolves to the number of peers it was pushed to
+	pub fn publish(
+		&mut self,
+		ctx: &mut Context<Self>,
+		inner_path: &str,
+		progress: Option<Recipient<PublishProgress>>,
+	) -> Result<ResponseActFuture<Self, usize>, Error> {
+		let limit = self.publish_limit;
+		if !is_valid_inner_path(inner_path) || !inner_path.ends_with("content.json") {
+			return Err(Error::MissingError);
+		}
+		let body = serde_bytes::ByteBuf::from(std::fs::read(self.site_path().join(inner_path))?);
+		// Pick up a version signed since we last loaded it
+		self.apply_content(inner_path, body.clone())?;
+		self.cleanup_peers();
+		if self.peers.len() < limit {
+			self.announce_pex(ctx);
+		}
+		let inner_path = String::from(inner_path);
+		let publisher = self.publisher(&inner_path, body, limit, progress);
+		let publisher = publisher
+			.into_actor(self)
+			.map(move |(published, stats), act, _ctx| {
+				for (key, stats) in stats {
+					act.set_stats(&key, stats);
+				}
+				info!(
+					"Published zero://{}/{} to {}/{} peers",
+					act.address.get_address_short(),
+					inner_path,
+					published,
+					limit
+				);
+				published
+			});
+		Ok(Box::pin(publisher))
+	}
+	// Push an update to our best peers, as many at once as are still needed,
+	// moving on to the next ones for every peer that fails, resolves to the
+	// number of peers it was pushed to and the stats of the peers tried.
+	// It goes without diffs, we don't keep the old versions of the files
+	fn publisher(
+		&self,
+		inner_path: &str,
+		body: serde_bytes::ByteBuf,
+		limit: usize,
+		progress: Option<Recipient<PublishProgress>>,
+	) -> LocalBoxFuture<'static, (usize, Vec<(String, PeerStats)>)> {
+		let modified = self.content_modified(inner_path).unwrap_or(0.0);
+		let peers = self.connectable_addrs(None);
+		let site_address = self.address.clone();
+		let inner_path = String::from(inner_path);
+		async move {
+			let mut published = 0;
+			let mut stats = vec![];
+			let mut next = 0;
+			while published < limit && next < peers.len() {
+				let batch = &peers[next..peers.len().min(next + limit - published)];
+				next += batch.len();
+				let requests = batch.iter().map(|(_, addr)| {
+					addr.send(Update {
+						site_address: site_address.clone(),
+						inner_path: inner_path.clone(),
+						body: body.clone(),
+						modified,
+						diffs: Diffs::new(),
+					})
+				});
+				let results = join_all(requests).await;
+				for ((key, addr), result) in batch.iter().zip(results) {
+					match result {
+						Ok(Ok(())) => {
+							published += 1;
+							if let Some(progress) = &progress {
+								let _ = progress.do_send(PublishProgress {
+									inner_path: inner_path.clone(),
+									published,
+									limit,
+								});
+							}
+						}
+						_ => trace!("Publishing to {} failed", key),
+					}
+					if let Ok(Ok(peer_stats)) = addr.send(PeerStatsRequest {}).await {
+						stats.push((key.clone(), peer_stats));
+					}
+				}
+			}
+			(published, stats)
+		}
+		.boxed_local()
+	}
+	// Copy the files of a cloneable site to a new site, the files below `root_inner_path`
+	// (or the site's clone root) end up at the root of the new site and `-default` files
+	// replace their counterparts, then the new content.json is signed with `privatekey`,
+	// derived from the user's master seed with `address_index`
+	pub fn clone(
+		&self,
+		address: &Address,
+		address_index: usize,
+		privatekey: &str,
+		root_inner_path: &str,
+	) -> Result<(), Error> {
+		let content = match &self.content {
+			Some(content) => content,
+			None => return Err(Error::MissingError),
+		};
+		if !content.cloneable {
+			return Err(Error::InvalidContent(String::from("Site is not cloneable")));
+		}
+		let root_inner_path = match root_inner_path {
+			"" => content.clone_root.trim_matches('/'),
+			root_inner_path => root_inner_path.trim_matches('/'),
+		};
+		if !root_inner_path.is_empty() && !is_valid_inner_path(root_inner_path) {
+			return Err(Error::InvalidContent(format!(
+				"Invalid clone root: {}",
+				root_inner_path
+			)));
+		}
+		let new_path = self.data_path.join(address.to_string());
+		if new_path.exists() {
+			error!("Refusing to clone into existing directory {:?}", new_path);
+			return Err(Error::MissingError);
+		}
+		info!(
+			"Cloning {} to {}",
+			self.address.get_address_short(),
+			address.get_address_short()
+		);
+		let result = self.clone_to(
+			content,
+			address,
+			address_index,
+			privatekey,
+			root_inner_path,
+			&new_path,
+		);
+		let copied = match result {
+			Ok(copied) => copied,
+			Err(err) => {
+				// Don't leave a half cloned site behind
+				if new_path.exists() {
+					if let Err(err) = std::fs::remove_dir_all(&new_path) {
+						error!("Could not remove {:?}: {:?}", new_path, err);
+					}
+				}
+				return Err(err);
+			}
+		};
+		info!(
+			"Cloned {} files of {} to {}",
+			copied,
+			self.address.get_address_short(),
+			address.get_address_short()
+		);
+		Ok(())
+	}
+	// Copy the files and sign the new content.json, returns the number of copied files
+	fn clone_to(
+		&self,
+		content: &Content,
+		address: &Address,
+		address_index: usize,
+		privatekey: &str,
+		root_inner_path: &str,
+		new_path: &Path,
+	) -> Result<usize, Error> {
+		let site_path = self.site_path();
+		std::fs::create_dir_all(new_path)?;
+		// Directories that have a -default counterpart are not copied
+		let default_dirs: Vec<String> = std::fs::read_dir(site_path.join(root_inner_path))?
+			.filter_map(|e| e.ok())
+			.filter(|entry| entry.path().is_dir())
+			.filter_map(|entry| {
+				let name = entry.file_name().to_string_lossy().to_string();
+				name.strip_suffix("-default").map(String::from)
+			})
+			.collect();
+		let files = std::iter::once(("content.json", content))
+			.chain(self.contents.iter().map(|(p, c)| (p.as_str(), c)))
+			.flat_map(|(content_path, content)| {
+				let dir = content_dir(content_path);
+				content
+					.files
+					.keys()
+					.map(move |path| format!("{}{}", dir, path))
+			});
+		let mut copied = 0;
+		for inner_path in files {
+			let dest_path = match root_inner_path {
+				"" => inner_path.as_str(),
+				root => match inner_path.strip_prefix(root) {
+					Some(path) if path.starts_with('/') => &path[1..],
+					_ => continue,
+				},
+			};
+			let first_dir = dest_path.split('/').next().unwrap_or("");
+			if dest_path == "content.json"
+				|| (dest_path.contains('/') && default_dirs.iter().any(|dir| dir == first_dir))
+			{
+				continue;
+			}
+			// -default files are kept as well, so the new site can be cloned too
+			let mut dest_paths = vec![String::from(dest_path)];
+			if dest_path.contains("-default") {
+				dest_paths.push(dest_path.replace("-default", ""));
+			}
+			for dest_path in dest_paths {
+				if !is_valid_inner_path(&dest_path) {
+					continue;
+				}
+				let source = site_path.join(&inner_path);
+				// Files that weren't downloaded yet are left out
+				if !source.is_file() {
+					trace!("Not cloning {}, it wasn't downloaded", inner_path);
+					continue;
+				}
+				let dest = new_path.join(&dest_path);
+				if let Some(parent) = dest.parent() {
+					std::fs::create_dir_all(parent)?;
+				}
+				std::fs::copy(source, dest)?;
+				copied += 1;
+			}
+		}
+		let mut new_content = content.cleared();
+		new_content.address = address.to_string();
+		new_content.address_index = address_index;
+		new_content.cloned_from = self.address.to_string();
+		new_content.clone_root = String::from(root_inner_path);
+		new_content.domain = String::new();
+		new_content.signers_sign = String::new();
+		new_content.files = BTreeMap::new();
+		new_content.files_optional = BTreeMap::new();
+		let mut paths = vec![];
+		list_files(new_path, "", &mut paths);
+		for inner_path in paths {
+			let name = inner_path.rsplit('/').next().unwrap_or("");
+			if name == "content.json"
+				|| name.starts_with('.')
+				|| name.ends_with("-old")
+				|| name.ends_with("-new")
+			{
+				continue;
+			}
+			if let Some(ignore) = &content.ignore {
+				if matches_start(ignore, &inner_path) {
+					continue;
+				}
+			}
+			let file = ContentFile {
+				sha512: file_hash(&new_path.join(&inner_path)).ok_or(Error::FileNotFound)?,
+				size: new_path.join(&inner_path).metadata()?.len() as usize,
+			};
+			if !content.optional.is_empty() && matches_start(&content.optional, &inner_path) {
+				new_content.files_optional.insert(inner_path, file);
+			} else {
+				new_content.files.insert(inner_path, file);
+			}
+		}
+		new_content.modified = Utc::now().timestamp() as f64;
+		let sign = new_content.sign(String::from(privatekey));
+		new_content.signs.insert(address.to_string(), sign);
+		if !new_content.verify(address.to_string()) {
+			return Err(Error::CryptError(String::from(
+				"Private key does not belong to the new site",
+			)));
+		}
+		std::fs::write(
+			new_path.join("content.json"),
+			serde_json::to_vec_pretty(&new_content)?,
+		)?;
+		Ok(copied)
+	}
 	pub fn pooled_need_file() {}
 	pub fn is_file_download_allowed() {}
-	pub fn need_file_info() {
-		// TODO: get info for file from content.json
+	// Get info for file from the content.json listing it
+	pub fn need_file_info(&self, inner_path: &str) -> Option<ContentFile> {
+		if let Some(file) = self.content.as_ref()?.get_file(inner_path) {
+			return Some(file);
+		}
+		self.contents.iter().find_map(|(content_path, content)| {
+			let path = inner_path.strip_prefix(content_dir(content_path))?;
+			content.get_file(path)
+		})
 	}
-	// Check and download if file not exist
-	pub fn need_file(&mut self, inner_path: &str) -> Result<bool, Error> {
+	// Queue the file for download if we don't have it yet,
+	// returns true if we have it already
+	pub fn need_file(&mut self, ctx: &mut Context<Self>, inner_path: &str) -> Result<bool, Error> {
 		// TODO: move site download to appropriate place
 		if self.content.is_none() {
-			self.download_site()?;
+			self.download_site(ctx)?;
 		}
-		let file_content = match self.content.as_ref().unwrap().get_file(inner_path) {
+		let file_content = match self.need_file_info(inner_path) {
 			Some(f) => f,
 			None => return Err(Error::MissingError),
 		};
-		// TODO: find file in content and prioritize appropriately
-		self.queued_files.push(String::from(inner_path));
-		// TODO: stop here, let queued files be downloaded by routine
-		let buf = self.download_file(inner_path)?;
-		let mut path = self.data_path.clone();
-		path.push(&self.address.to_string());
-		path.push(inner_path);
-		// TODO: check if we need to take the parent here
-		trace!("{:?}", std::fs::create_dir_all(path.parent().unwrap()));
-		let mut file = match std::fs::File::create(&path) {
-			Ok(f) => f,
+		if self.site_path().join(inner_path).is_file() {
+			return Ok(true);
+		}
+		let optional = self.optional_hash_id(inner_path).is_some();
+		let priority =
+			Priority::from_path(Path::new(inner_path), optional).unwrap_or(Priority::Required);
+		self
+			.queue
+			.add(FileRequest::new(inner_path, priority, Some(file_content)));
+		Ok(false)
+	}
+	// Start downloading queued files, most urgent first, until all workers are busy
+	fn run_workers(&mut self, ctx: &mut Context<Self>) {
+		if !self.is_serving() {
+			return;
+		}
+		while self.queue.downloading() < MAX_WORKERS {
+			let inner_path = match self.queue.start_next() {
+				Some(inner_path) => inner_path,
+				None => return,
+			};
+			// The file is counted as downloading already
+			if !self.fits_size_limit(0) {
+				trace!(
+					"Size limit of {}MB reached for {}, not downloading {}",
+					self.get_size_limit(),
+					self.address.get_address_short(),
+					inner_path
+				);
+				self.queue.requeue(&inner_path);
+				return;
+			}
+			let key = match self.worker_peer(ctx, &inner_path) {
+				Some(key) => key,
+				None => {
+					trace!(
+						"No peers to download zero://{}/{} from",
+						self.address.get_address_short(),
+						inner_path
+					);
+					self.queue.requeue(&inner_path);
+					return;
+				}
+			};
+			let file = self
+				.queue
+				.get(&inner_path)
+				.and_then(|task| task.file.clone());
+			let addr = self.peers[&key].addr.clone();
+			// Files are streamed to a temp file, content.json files are kept in memory
+			let download: LocalBoxFuture<'static, Result<Download, Error>> = match file {
+				Some(file) => {
+					let temp_path = match self.temp_path(&inner_path) {
+						Ok(temp_path) => temp_path,
+						Err(err) => {
+							self.worker_done(&inner_path, &key, Err(err));
+							continue;
+						}
+					};
+					let req = FileDownload {
+						inner_path: inner_path.clone(),
+						site_address: self.address.clone(),
+						file_size: file.size,
+						path: temp_path.clone(),
+					};
+					async move {
+						let downloaded = addr.send(req).await??;
+						Ok(Download::File(temp_path, downloaded))
+					}
+					.boxed_local()
+				}
+				None => {
+					let req = crate::peer::FileGetRequest {
+						inner_path: inner_path.clone(),
+						site_address: self.address.clone(),
+						file_size: 0,
+						progress: None,
+					};
+					async move { Ok(Download::Content(addr.send(req).await??)) }.boxed_local()
+				}
+			};
+			// The peer's stats are picked up along with the download to rank it
+			let stats_addr = self.peers[&key].addr.clone();
+			let download = async move {
+				let result = download.await;
+				(result, stats_addr.send(PeerStatsRequest {}).await)
+			};
+			let download = download
+				.into_actor(self)
+				.map(move |(result, stats), act, ctx| {
+					if let Ok(Ok(stats)) = stats {
+						act.set_stats(&key, stats);
+					}
+					act.worker_done(&inner_path, &key, result);
+					act.send_my_hashfield(ctx);
+					act.run_workers(ctx);
+				});
+			ctx.spawn(download);
+		}
+	}
+	// Best peer that hasn't failed to serve the file yet, starting over once all have.
+	// None while peers are asked who has an optional file nobody is known to have
+	fn worker_peer(&mut self, ctx: &mut Context<Self>, inner_path: &str) -> Option<String> {
+		let hash_id = self.optional_hash_id(inner_path);
+		if let Some(hash_id) = hash_id {
+			if !self.peers.values().any(|p| p.has_hash_id(hash_id))
+				&& self.hash_id_lookups.insert(hash_id)
+			{
+				self.find_hash_ids(ctx, vec![hash_id]);
+				return None;
+			}
+		}
+		let peers = self.get_connectable_peers(hash_id);
+		let tried = &self.queue.get(inner_path)?.tried_peers;
+		if let Some(key) = peers.iter().find(|key| !tried.contains(key)) {
+			return Some(key.clone());
+		}
+		self.queue.clear_tried_peers(inner_path);
+		peers.into_iter().next()
+	}
+	// Store the file a worker downloaded, counting a failed try if that didn't work out
+	fn worker_done(&mut self, inner_path: &str, key: &str, result: Result<Download, Error>) {
+		match result.and_then(|download| self.save_download(inner_path, key, download)) {
+			Ok(()) => {
+				self.queue.done(inner_path);
+				self.settings.cache.bad_files.remove(inner_path);
+			}
 			Err(err) => {
-				error!("Error creating '{:?}': {:?}", &path, err);
+				*self
+					.settings
+					.cache
+					.bad_files
+					.entry(String::from(inner_path))
+					.or_insert(0) += 1;
+				trace!(
+					"Downloading zero://{}/{} from {} failed: {:?}",
+					self.address.get_address_short(),
+					inner_path,
+					key,
+					err
+				);
+				if self.queue.failed(inner_path, Some(key)) {
+					error!(
+						"Giving up on zero://{}/{} after {} tries",
+						self.address.get_address_short(),
+						inner_path,
+						MAX_TRIES
+					);
+				}
+			}
+		}
+	}
+	// Verify a downloaded file and move it into place, content.json files
+	// are verified by their signature and have their files queued
+	fn save_download(
+		&mut self,
+		inner_path: &str,
+		key: &str,
+		download: Download,
+	) -> Result<(), Error> {
+		let file = match self.queue.get(inner_path) {
+			Some(task) => task.file.clone(),
+			None => None,
+		};
+		let (temp_path, downloaded, file) = match (download, file) {
+			(Download::Content(buf), None) => {
+				let old_content = self.get_content(inner_path).cloned();
+				if self.apply_content(inner_path, buf)? && old_content.is_some() {
+					self.queue_changed_files(inner_path, old_content);
+				}
+				self.queue_content_files(inner_path);
+				return Ok(());
+			}
+			(Download::File(temp_path, downloaded), Some(file)) => (temp_path, downloaded, file),
+			(Download::File(temp_path, _), None) => {
+				let _ = std::fs::remove_file(temp_path);
 				return Err(Error::MissingError);
 			}
+			(Download::Content(_), Some(_)) => return Err(Error::MissingError),
 		};
-		if buf.len() != file_content.size {
-			error!("Wrong filesize!");
-			return Err(Error::MissingError);
-		}
-		let mut hasher = sha2::Sha512::default();
-		use sha2::Digest;
-		hasher.input(&buf);
-		let mut hash_result = hex::encode(hasher.result());
-		hash_result.truncate(64);
-		if hash_result != file_content.sha512 {
+		if downloaded.size != file.size || downloaded.sha512 != file.sha512 {
 			error!(
-				"Wrong filehash: {} != {}",
-				&hash_result, &file_content.sha512
+				"zero://{}/{} from {} does not match its hash",
+				self.address.get_address_short(),
+				inner_path,
+				key
 			);
+			let _ = std::fs::remove_file(temp_path);
+			self.bad_file(key, inner_path);
 			return Err(Error::MissingError);
 		}
-		file.write_all(&buf)?;
-		return Ok(true);
+		self.move_site_file(inner_path, &temp_path)?;
+		if let Some(hash_id) = self.optional_hash_id(inner_path) {
+			// Sent to peers once the worker is done
+			if self.hashfield.add(hash_id) {
+				self.hashfield_changed = true;
+			}
+		}
+		Ok(())
+	}
+	// Temp file a file of the site is written to before it's moved into place,
+	// outside of the site directory so it's never served
+	fn temp_path(&self, inner_path: &str) -> Result<PathBuf, Error> {
+		if !is_valid_inner_path(inner_path) {
+			error!("Refusing to write invalid path {}", inner_path);
+			return Err(Error::MissingError);
+		}
+		let path = self
+			.data_path
+			.join(TEMP_DIR)
+			.join(self.address.to_string())
+			.join(inner_path);
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent)?;
+		}
+		Ok(path)
+	}
+	// Write a file of the site through a temp file, so it's never seen half written
+	fn write_site_file(&mut self, inner_path: &str, buf: &[u8]) -> Result<(), Error> {
+		let temp_path = self.temp_path(inner_path)?;
+		if let Err(err) = std::fs::write(&temp_path, buf) {
+			error!("Error writing '{:?}': {:?}", &temp_path, err);
+			let _ = std::fs::remove_file(&temp_path);
+			return Err(Error::MissingError);
+		}
+		self.move_site_file(inner_path, &temp_path)
+	}
+	// Move a verified temp file into place, keeping track of the size of the site
+	fn move_site_file(&mut self, inner_path: &str, temp_path: &Path) -> Result<(), Error> {
+		let path = self.site_path().join(inner_path);
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent)?;
+		}
+		let old_size = path.metadata().map_or(0, |m| m.len() as usize);
+		let size = temp_path.metadata()?.len() as usize;
+		if let Err(err) = std::fs::rename(temp_path, &path) {
+			error!("Error moving '{:?}' into place: {:?}", &path, err);
+			let _ = std::fs::remove_file(temp_path);
+			return Err(Error::MissingError);
+		}
+		self.settings.size = (self.settings.size + size).saturating_sub(old_size);
+		Ok(())
+	}
+	// Hash id of the file if it is an optional file
+	fn optional_hash_id(&self, inner_path: &str) -> Option<u16> {
+		let file = self.content.as_ref()?.files_optional.get(inner_path)?;
+		hash_id(&file.sha512)
+	}
+	// Start from the hashfield saved in sites.json, so optional files
+	// we have are found before the site's files are checked
+	fn load_cached_hashfield(&mut self) {
+		match base64::decode(&self.settings.cache.hashfield) {
+			Ok(bytes) => self.hashfield = Hashfield::from_bytes(&bytes),
+			Err(err) => error!("Invalid hashfield in sites.json: {:?}", err),
+		}
+	}
+	// Add the optional files we already have to our hashfield
+	fn load_hashfield(&mut self) {
+		let content = match &self.content {
+			Some(content) => content,
+			None => return,
+		};
+		let site_path = self.site_path();
+		for (inner_path, file) in content.files_optional.iter() {
+			if !site_path.join(inner_path).is_file() {
+				continue;
+			}
+			if let Some(hash_id) = hash_id(&file.sha512) {
+				if self.hashfield.add(hash_id) {
+					self.hashfield_changed = true;
+				}
+			}
+		}
 	}
 	pub fn add_peer() {}
-	pub fn announce() {}
+	// Let our peers and the trackers know we're still around and find new ones
+	pub fn announce(&mut self, ctx: &mut Context<Self>) {
+		self.cleanup_peers();
+		if let Some(site_manager) = &self.site_manager {
+			site_manager.do_send(AnnounceSite {
+				address: self.address.clone(),
+			});
+		}
+		self.announce_pex(ctx);
+		self.send_my_hashfield(ctx);
+	}
+	// Ask connected peers for more peers, the ones received are added by the peer manager
+	pub fn announce_pex(&mut self, ctx: &mut Context<Self>) {
+		let addresses: Vec<String> = self.peers.values().map(|p| p.address.clone()).collect();
+		let keys = self.get_connectable_peers(None);
+		let requests: Vec<(String, Addr<Peer>, Pex)> = keys
+			.iter()
+			.filter_map(|k| self.peers.get_key_value(k))
+			.map(|(key, peer)| {
+				let msg = Pex {
+					site_address: self.address.clone(),
+					peers: addresses
+						.iter()
+						.filter(|a| **a != peer.address)
+						.take(PEX_NEED)
+						.cloned()
+						.collect(),
+					need: PEX_NEED,
+				};
+				(key.clone(), peer.addr.clone(), msg)
+			})
+			.collect();
+		let pex = async move {
+			let mut received = 0;
+			for (key, addr, msg) in requests {
+				match addr.send(msg).await {
+					Ok(Ok(n)) => received += n,
+					_ => trace!("Pex with {} failed", key),
+				}
+				if received >= PEX_NEED {
+					break;
+				}
+			}
+			received
+		};
+		let pex = pex.into_actor(self).map(|received, act, _ctx| {
+			trace!(
+				"Announced {} to {} peers, received {} peers",
+				act.address.get_address_short(),
+				act.peers.len(),
+				received
+			);
+		});
+		ctx.spawn(pex);
+	}
 	pub fn need_connections() {}
-	pub fn get_connectable_peers() {}
+	// The best peers up to the connection limit, see ranked_peers
+	pub fn get_connectable_peers(&self, hash_id: Option<u16>) -> Vec<String> {
+		self
+			.ranked_peers(hash_id)
+			.into_iter()
+			.filter(|key| self.peers[key].addr.connected())
+			.take(self.connected_limit)
+			.collect()
+	}
+	// The connectable peers with their actor, for requests made outside of the site actor
+	fn connectable_addrs(&self, hash_id: Option<u16>) -> Vec<(String, Addr<Peer>)> {
+		self
+			.get_connectable_peers(hash_id)
+			.into_iter()
+			.filter_map(|key| {
+				let addr = self.peers.get(&key)?.addr.clone();
+				Some((key, addr))
+			})
+			.collect()
+	}
 	pub fn get_recent_peers() {}
 	pub fn get_connected_peers() {}
-	pub fn cleanup_peers() {}
-	pub fn send_my_hashfield() {}
-	pub fn update_hashfield() {}
+	// Drop peers whose actor has been stopped by the peer manager
+	pub fn cleanup_peers(&mut self) {
+		let before = self.peers.len();
+		self.peers.retain(|_, peer| peer.addr.connected());
+		if self.peers.len() < before {
+			trace!(
+				"Dropped {} stopped peers of {}",
+				before - self.peers.len(),
+				self.address.get_address_short()
+			);
+		}
+	}
+	// Send our hashfield to peers if it has changed since it was last sent
+	pub fn send_my_hashfield(&mut self, ctx: &mut Context<Self>) {
+		if !self.hashfield_changed {
+			return;
+		}
+		// Changes made while it's being sent go out with the next one
+		self.hashfield_changed = false;
+		let mut peers = self.connectable_addrs(None);
+		peers.truncate(HASHFIELD_PEERS);
+		let requests = peers.iter().map(|(_, addr)| {
+			addr.send(SetHashfield {
+				site_address: self.address.clone(),
+				hashfield: self.hashfield.clone(),
+			})
+		});
+		let send = join_all(requests).into_actor(self);
+		let send = send.map(move |results, act, _ctx| {
+			let mut sent = 0;
+			for ((key, _), result) in peers.iter().zip(results) {
+				match result {
+					Ok(Ok(())) => sent += 1,
+					_ => trace!("Sending hashfield to {} failed", key),
+				}
+			}
+			if sent == 0 {
+				act.hashfield_changed = true;
+			}
+		});
+		ctx.spawn(send);
+	}
+	// Look for peers with the optional files of the hash ids, in the hashfields of the
+	// peers we have none of yet and otherwise with findHashIds, the workers are run
+	// again once the answers are in
+	fn find_hash_ids(&mut self, ctx: &mut Context<Self>, hash_ids: Vec<u16>) {
+		let peers = self.connectable_addrs(None);
+		let mut hashfield_peers: Vec<(String, Addr<Peer>)> = peers
+			.iter()
+			.filter(|(key, _)| self.peers[key].hashfield.is_none())
+			.cloned()
+			.collect();
+		hashfield_peers.truncate(HASHFIELD_PEERS);
+		let site_address = self.address.clone();
+		let lookup = async move {
+			let requests = hashfield_peers.iter().map(|(_, addr)| {
+				addr.send(GetHashfield {
+					site_address: site_address.clone(),
+				})
+			});
+			let mut hashfields: Vec<(String, Hashfield)> = vec![];
+			for ((key, _), result) in hashfield_peers.iter().zip(join_all(requests).await) {
+				match result {
+					Ok(Ok(hashfield)) => hashfields.push((key.clone(), hashfield)),
+					_ => trace!("Hashfield request to {} failed", key),
+				}
+			}
+			let mut missing: Vec<u16> = hash_ids
+				.into_iter()
+				.filter(|id| !hashfields.iter().any(|(_, h)| h.contains(*id)))
+				.collect();
+			let mut found: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+			for (key, addr) in peers {
+				if missing.is_empty() {
+					break;
+				}
+				let msg = FindHashIds {
+					site_address: site_address.clone(),
+					hash_ids: missing.clone(),
+				};
+				match addr.send(msg).await {
+					Ok(Ok(res)) => {
+						for (hash_id, addresses) in res {
+							found.entry(hash_id).or_default().extend(addresses);
+						}
+					}
+					_ => trace!("findHashIds with {} failed", key),
+				}
+				missing.retain(|id| !found.contains_key(id));
+			}
+			(hashfields, found)
+		};
+		let lookup = lookup
+			.into_actor(self)
+			.map(|(hashfields, found), act, ctx| {
+				for (key, hashfield) in hashfields {
+					if let Some(peer) = act.peers.get_mut(&key) {
+						peer.hashfield = Some(hashfield);
+					}
+				}
+				// New peers are added by the peer manager,
+				// mark the ones we already know as having the files
+				for (hash_id, addresses) in found.iter() {
+					for peer in act.peers.values_mut() {
+						if addresses.contains(&peer.address) {
+							peer
+								.hashfield
+								.get_or_insert_with(Hashfield::new)
+								.add(*hash_id);
+						}
+					}
+				}
+				act.run_workers(ctx);
+			});
+		ctx.spawn(lookup);
+	}
 	pub fn is_downloadable() {}
-	pub fn delete() {}
+	// Stop serving the site and remove its files
+	pub fn delete(&mut self) -> Result<(), Error> {
+		self.settings.serving = false;
+		self.queue = DownloadQueue::new();
+		self.peers.clear();
+		self.content = None;
+		self.contents.clear();
+		remove_site_files(&self.data_path, &self.address)
+	}
 	pub fn add_event_listener() {}
 	pub fn update_websocket() {}
 	pub fn message_websocket() {}
@@ -183,6 +1736,149 @@ impl Site {
 
 impl Actor for Site {
 	type Context = Context<Self>;
+
+	fn started(&mut self, ctx: &mut Context<Self>) {
+		self.load_cached_hashfield();
+		self.schedule_update(ctx, self.update_interval());
+		ctx.run_interval(WORKER_INTERVAL, |act, ctx| act.run_workers(ctx));
+		ctx.run_interval(BAD_FILES_INTERVAL, |act, ctx| {
+			if act.retry_bad_files() > 0 {
+				act.run_workers(ctx);
+			}
+		});
+	}
+}
+
+// What a worker received from a peer
+enum Download {
+	// content.json files are verified by their signature, so they're kept in memory
+	Content(serde_bytes::ByteBuf),
+	// Other files are written to a temp file, hashed along the way
+	File(PathBuf, DownloadedFile),
+}
+
+// Remove the temp files of downloads that were interrupted
+pub fn clean_temp_files(data_path: &Path) {
+	let temp_dir = data_path.join(TEMP_DIR);
+	if !temp_dir.is_dir() {
+		return;
+	}
+	match std::fs::remove_dir_all(&temp_dir) {
+		Ok(()) => info!("Removed temp files of unfinished downloads"),
+		Err(err) => error!("Could not remove temp files in {:?}: {:?}", temp_dir, err),
+	}
+}
+
+// Remove the files of a site, along with the temp files of its downloads
+pub fn remove_site_files(data_path: &Path, address: &Address) -> Result<(), Error> {
+	let temp_dir = data_path.join(TEMP_DIR).join(address.to_string());
+	if temp_dir.is_dir() {
+		if let Err(err) = std::fs::remove_dir_all(&temp_dir) {
+			error!("Could not remove temp files in {:?}: {:?}", temp_dir, err);
+		}
+	}
+	let site_path = data_path.join(address.to_string());
+	if site_path.is_dir() {
+		std::fs::remove_dir_all(&site_path)?;
+	}
+	info!("Removed files of {}", address.get_address_short());
+	Ok(())
+}
+
+// Time to wait before retrying a bad file that failed the given number of tries
+fn bad_file_retry_delay(tries: usize) -> Duration {
+	let rounds = (tries / MAX_TRIES).min(16) as u32;
+	Duration::seconds((BAD_FILE_RETRY_DELAY << rounds).min(BAD_FILE_MAX_RETRY_DELAY))
+}
+
+// Directory of a content.json file, including the trailing slash
+fn content_dir(inner_path: &str) -> &str {
+	match inner_path.rfind('/') {
+		Some(i) => &inner_path[..=i],
+		None => "",
+	}
+}
+
+// Whether a file listed in a content.json is new or changed since `old_files`
+fn is_changed(
+	old_files: Option<&BTreeMap<String, ContentFile>>,
+	path: &str,
+	file: &ContentFile,
+) -> bool {
+	match old_files {
+		Some(old_files) => old_files.get(path) != Some(file),
+		None => true,
+	}
+}
+
+// Sha512 of the buffer truncated to 64 hex characters, as used in content.json
+fn hash(buf: &[u8]) -> String {
+	use sha2::Digest;
+	let mut hasher = sha2::Sha512::default();
+	hasher.input(buf);
+	let mut hash_result = hex::encode(hasher.result());
+	hash_result.truncate(64);
+	hash_result
+}
+
+fn file_hash(path: &Path) -> Option<String> {
+	let mut file = std::fs::File::open(path).ok()?;
+	let mut hasher = HashWriter::new(std::io::sink());
+	std::io::copy(&mut file, &mut hasher).ok()?;
+	Some(hasher.finish().1)
+}
+
+// Total size of the files below dir
+fn dir_size(dir: &Path) -> usize {
+	let entries = match std::fs::read_dir(dir) {
+		Ok(e) => e,
+		Err(_) => return 0,
+	};
+	entries
+		.filter_map(|e| e.ok())
+		.map(|entry| match entry.metadata() {
+			Ok(m) if m.is_dir() => dir_size(&entry.path()),
+			Ok(m) => m.len() as usize,
+			Err(_) => 0,
+		})
+		.sum()
+}
+
+// Collect the inner paths of the files below dir, leaving out
+// the directories with a content.json file of their own
+fn list_files(dir: &Path, prefix: &str, paths: &mut Vec<String>) {
+	let entries = match std::fs::read_dir(dir) {
+		Ok(e) => e,
+		Err(_) => return,
+	};
+	for entry in entries.filter_map(|e| e.ok()) {
+		let name = entry.file_name().to_string_lossy().to_string();
+		let path = entry.path();
+		if path.is_dir() {
+			if !path.join("content.json").is_file() {
+				list_files(&path, &format!("{}{}/", prefix, name), paths);
+			}
+		} else {
+			paths.push(format!("{}{}", prefix, name));
+		}
+	}
+}
+
+// Collect the inner paths of all content.json files below dir
+fn find_content_files(dir: &Path, prefix: &str, paths: &mut Vec<String>) {
+	let entries = match std::fs::read_dir(dir) {
+		Ok(e) => e,
+		Err(_) => return,
+	};
+	for entry in entries.filter_map(|e| e.ok()) {
+		let name = entry.file_name().to_string_lossy().to_string();
+		let path = entry.path();
+		if path.is_dir() {
+			find_content_files(&path, &format!("{}{}/", prefix, name), paths);
+		} else if name == "content.json" {
+			paths.push(format!("{}{}", prefix, name));
+		}
+	}
 }
 
 pub struct SiteInfoRequest {}
@@ -200,18 +1896,25 @@ impl Handler<SiteInfoRequest> for Site {
 			self.download_content("content.json")?;
 		}
 		Ok(SiteInfo {
-			tasks: self.queued_files.len(),
-			size_limit: 10,
+			tasks: self.queue.len(),
+			size_limit: self.get_size_limit(),
 			address: self.address.to_string(),
 			address_short: self.address.get_address_short(),
-			next_size_limit: 10,
+			next_size_limit: self.get_next_size_limit(),
 			auth_address: String::from("test"),
 			auth_key_sha512: String::from("test"),
 			peers: self.peers.len() + 1, // TODO: only add 1 if hosting zite
 			auth_key: String::from("test"),
-			settings: self.settings.clone(),
-			bad_files: 0,
-			workers: 0,
+			// Reported as the size of the whole site, so the user is asked
+			// for a bigger limit when it doesn't fit
+			settings: SiteSettings {
+				size: self.get_size_needed(),
+				auth_key: String::new(),
+				wrapper_key: String::new(),
+				..self.settings.clone()
+			},
+			bad_files: self.settings.cache.bad_files.len(),
+			workers: self.queue.downloading(),
 			content: site_info::SiteContentSummary::from_content(&self.content.as_ref().unwrap()),
 			started_task_num: 0,
 			content_updated: 0f64,
@@ -226,12 +1929,14 @@ impl Handler<SiteInfoRequest> for Site {
 /// let msg = AddPeer{
 /// 	peer_id: "peerID",
 /// 	peer_addr: peer.addr(),
+/// 	address: "192.168.1.1:5432",
 /// };
 /// site.send(msg);
 /// ```
 pub struct AddPeer {
-	peer_id: String,
-	peer_addr: Addr<Peer>,
+	pub peer_id: String,
+	pub peer_addr: Addr<Peer>,
+	pub address: String,
 }
 
 impl Message for AddPeer {
@@ -242,7 +1947,13 @@ impl Handler<AddPeer> for Site {
 	type Result = Result<(), ()>;
 
 	fn handle(&mut self, msg: AddPeer, ctx: &mut Context<Self>) -> Self::Result {
-		let prev = self.peers.insert(msg.peer_id.clone(), msg.peer_addr);
+		let peer = SitePeer {
+			addr: msg.peer_addr,
+			address: msg.address,
+			hashfield: None,
+			stats: PeerStats::default(),
+		};
+		let prev = self.peers.insert(msg.peer_id.clone(), peer);
 		if prev.is_none() {
 			trace!(
 				"Added {} as peer for {}",
@@ -254,6 +1965,349 @@ impl Handler<AddPeer> for Site {
 	}
 }
 
+/// Message struct used to request the settings of a site, to be persisted
+pub struct SiteSettingsRequest {}
+
+impl Message for SiteSettingsRequest {
+	type Result = Result<SiteSettings, Error>;
+}
+
+impl Handler<SiteSettingsRequest> for Site {
+	type Result = Result<SiteSettings, Error>;
+
+	fn handle(&mut self, _msg: SiteSettingsRequest, _ctx: &mut Context<Self>) -> Self::Result {
+		let mut settings = SiteSettings {
+			peers: self.peers.len(),
+			..self.settings.clone()
+		};
+		settings.cache.hashfield = base64::encode(&self.hashfield.to_bytes());
+		Ok(settings)
+	}
+}
+
+/// Message struct used to request the addresses of a site's peers
+pub struct SitePeersRequest {}
+
+impl Message for SitePeersRequest {
+	type Result = Result<Vec<String>, Error>;
+}
+
+impl Handler<SitePeersRequest> for Site {
+	type Result = Result<Vec<String>, Error>;
+
+	fn handle(&mut self, _msg: SitePeersRequest, _ctx: &mut Context<Self>) -> Self::Result {
+		Ok(self.peers.values().map(|p| p.address.clone()).collect())
+	}
+}
+
+/// Message struct used to update a site from its peers,
+/// returns the number of modified content.json files queued
+pub struct SiteUpdateRequest {}
+
+impl Message for SiteUpdateRequest {
+	type Result = Result<usize, Error>;
+}
+
+impl Handler<SiteUpdateRequest> for Site {
+	type Result = ResponseActFuture<Self, Result<usize, Error>>;
+
+	fn handle(&mut self, _msg: SiteUpdateRequest, ctx: &mut Context<Self>) -> Self::Result {
+		if self.content.is_none() {
+			if let Err(err) = self.download_site(ctx) {
+				return Box::pin(fut::err(err));
+			}
+		}
+		Box::pin(self.update().map(|queued, _act, _ctx| Ok(queued)))
+	}
+}
+
+/// Message struct used to pass on a content.json file a peer pushed to us,
+/// returns false if it was not newer than ours
+pub struct ContentUpdate {
+	pub inner_path: String,
+	pub body: serde_bytes::ByteBuf,
+	pub diffs: Diffs,
+	// Address of the peer that sent the update, it is not relayed back to it
+	pub sender: Option<String>,
+}
+
+impl Message for ContentUpdate {
+	type Result = Result<bool, Error>;
+}
+
+impl Handler<ContentUpdate> for Site {
+	type Result = Result<bool, Error>;
+
+	fn handle(&mut self, msg: ContentUpdate, ctx: &mut Context<Self>) -> Self::Result {
+		let updated =
+			self.receive_update(&msg.inner_path, msg.body, msg.diffs, msg.sender.as_deref())?;
+		if updated {
+			self.run_workers(ctx);
+		}
+		Ok(updated)
+	}
+}
+
+/// Message struct used to push a content.json file of a site to its peers,
+/// returns the number of peers it was pushed to
+pub struct SitePublish {
+	pub inner_path: String,
+	// Receives a PublishProgress message for every peer reached
+	pub progress: Option<Recipient<PublishProgress>>,
+}
+
+impl Message for SitePublish {
+	type Result = Result<usize, Error>;
+}
+
+pub struct PublishProgress {
+	pub inner_path: String,
+	pub published: usize,
+	pub limit: usize,
+}
+
+impl Message for PublishProgress {
+	type Result = ();
+}
+
+impl Handler<SitePublish> for Site {
+	type Result = ResponseActFuture<Self, Result<usize, Error>>;
+
+	fn handle(&mut self, msg: SitePublish, ctx: &mut Context<Self>) -> Self::Result {
+		match self.publish(ctx, &msg.inner_path, msg.progress) {
+			Ok(publish) => Box::pin(publish.map(|published, _act, _ctx| Ok(published))),
+			Err(err) => Box::pin(fut::err(err)),
+		}
+	}
+}
+
+/// Message struct used to clone a cloneable site into a new site,
+/// signed with the private key of the new site
+pub struct SiteClone {
+	pub address: Address,
+	// BIP32 index the private key is derived with
+	pub address_index: usize,
+	pub privatekey: String,
+	// Directory to clone, the clone root of the site if empty
+	pub root_inner_path: String,
+}
+
+impl Message for SiteClone {
+	type Result = Result<(), Error>;
+}
+
+impl Handler<SiteClone> for Site {
+	type Result = Result<(), Error>;
+
+	fn handle(&mut self, msg: SiteClone, _ctx: &mut Context<Self>) -> Self::Result {
+		self.clone(
+			&msg.address,
+			msg.address_index,
+			&msg.privatekey,
+			&msg.root_inner_path,
+		)
+	}
+}
+
+/// Message struct used to delete a site, the site actor
+/// is stopped once its files are removed
+pub struct SiteDelete {}
+
+impl Message for SiteDelete {
+	type Result = Result<(), Error>;
+}
+
+impl Handler<SiteDelete> for Site {
+	type Result = Result<(), Error>;
+
+	fn handle(&mut self, _msg: SiteDelete, ctx: &mut Context<Self>) -> Self::Result {
+		let result = self.delete();
+		ctx.stop();
+		System::current().stop();
+		result
+	}
+}
+
+/// Message struct used to pause or resume a site
+pub struct SiteSetServing {
+	pub serving: bool,
+}
+
+impl Message for SiteSetServing {
+	type Result = Result<(), Error>;
+}
+
+impl Handler<SiteSetServing> for Site {
+	type Result = Result<(), Error>;
+
+	fn handle(&mut self, msg: SiteSetServing, ctx: &mut Context<Self>) -> Self::Result {
+		info!(
+			"{} {}",
+			if msg.serving { "Resuming" } else { "Pausing" },
+			self.address.get_address_short()
+		);
+		self.settings.serving = msg.serving;
+		self.run_workers(ctx);
+		Ok(())
+	}
+}
+
+/// Message struct used to set the size limit of a site in MB,
+/// limits below the size of the site are refused
+pub struct SiteSetLimit {
+	pub size_limit: usize,
+}
+
+impl Message for SiteSetLimit {
+	type Result = Result<(), Error>;
+}
+
+impl Handler<SiteSetLimit> for Site {
+	type Result = Result<(), Error>;
+
+	fn handle(&mut self, msg: SiteSetLimit, ctx: &mut Context<Self>) -> Self::Result {
+		if msg.size_limit.saturating_mul(1024 * 1024) < self.settings.size {
+			return Err(Error::SizeLimit);
+		}
+		info!(
+			"Size limit of {} set to {}MB",
+			self.address.get_address_short(),
+			msg.size_limit
+		);
+		self.settings.size_limit = msg.size_limit;
+		self.run_workers(ctx);
+		Ok(())
+	}
+}
+
+/// Message struct used to verify all files of a site, queueing the bad ones
+/// to be downloaded again and optionally deleting the unlisted ones
+pub struct SiteVerify {
+	pub delete_unlisted: bool,
+}
+
+impl Message for SiteVerify {
+	type Result = Result<VerifyReport, Error>;
+}
+
+impl Handler<SiteVerify> for Site {
+	type Result = Result<VerifyReport, Error>;
+
+	fn handle(&mut self, msg: SiteVerify, ctx: &mut Context<Self>) -> Self::Result {
+		let report = self.update_bad_files(msg.delete_unlisted)?;
+		if report.queued > 0 {
+			self.run_workers(ctx);
+		}
+		Ok(report)
+	}
+}
+
+/// Message struct used to look up the content.json files of a site
+/// modified since a timestamp
+pub struct ModifiedContentsRequest {
+	pub since: f64,
+}
+
+impl Message for ModifiedContentsRequest {
+	type Result = Result<BTreeMap<String, f64>, Error>;
+}
+
+impl Handler<ModifiedContentsRequest> for Site {
+	type Result = Result<BTreeMap<String, f64>, Error>;
+
+	fn handle(&mut self, msg: ModifiedContentsRequest, _ctx: &mut Context<Self>) -> Self::Result {
+		let mut modified_files = BTreeMap::new();
+		if let Some(content) = &self.content {
+			modified_files.insert(String::from("content.json"), content.modified);
+		}
+		for (inner_path, content) in self.contents.iter() {
+			modified_files.insert(inner_path.clone(), content.modified);
+		}
+		modified_files.retain(|_, modified| *modified > msg.since);
+		Ok(modified_files)
+	}
+}
+
+/// Message struct used to request our hashfield of a site
+pub struct SiteHashfieldRequest {}
+
+impl Message for SiteHashfieldRequest {
+	type Result = Result<Hashfield, Error>;
+}
+
+impl Handler<SiteHashfieldRequest> for Site {
+	type Result = Result<Hashfield, Error>;
+
+	fn handle(&mut self, _msg: SiteHashfieldRequest, _ctx: &mut Context<Self>) -> Self::Result {
+		Ok(self.hashfield.clone())
+	}
+}
+
+/// Message struct used to store the hashfield a peer sent us,
+/// returns false if the peer is not known for the site
+pub struct SetPeerHashfield {
+	pub address: String,
+	pub hashfield: Hashfield,
+}
+
+impl Message for SetPeerHashfield {
+	type Result = Result<bool, Error>;
+}
+
+impl Handler<SetPeerHashfield> for Site {
+	type Result = Result<bool, Error>;
+
+	fn handle(&mut self, msg: SetPeerHashfield, _ctx: &mut Context<Self>) -> Self::Result {
+		match self.peers.values_mut().find(|p| p.address == msg.address) {
+			Some(peer) => {
+				peer.hashfield = Some(msg.hashfield);
+				Ok(true)
+			}
+			None => Ok(false),
+		}
+	}
+}
+
+/// Message struct used to look up the peers known to have
+/// the optional files with the given hash ids
+pub struct FindHashIdsRequest {
+	pub hash_ids: Vec<u16>,
+	// Our own address, included for the hash ids we have
+	pub my_address: Option<String>,
+}
+
+impl Message for FindHashIdsRequest {
+	type Result = Result<BTreeMap<u16, Vec<String>>, Error>;
+}
+
+impl Handler<FindHashIdsRequest> for Site {
+	type Result = Result<BTreeMap<u16, Vec<String>>, Error>;
+
+	fn handle(&mut self, msg: FindHashIdsRequest, _ctx: &mut Context<Self>) -> Self::Result {
+		let mut found = BTreeMap::new();
+		for hash_id in msg.hash_ids {
+			let mut addresses: Vec<String> = msg
+				.my_address
+				.iter()
+				.filter(|_| self.hashfield.contains(hash_id))
+				.cloned()
+				.collect();
+			addresses.extend(
+				self
+					.peers
+					.values()
+					.filter(|p| p.has_hash_id(hash_id))
+					.map(|p| p.address.clone())
+					.take(FIND_HASH_IDS_LIMIT - addresses.len()),
+			);
+			if !addresses.is_empty() {
+				found.insert(hash_id, addresses);
+			}
+		}
+		Ok(found)
+	}
+}
+
 /// Message struct used to request a file from a site
 /// ```
 /// match result {
@@ -266,7 +2320,7 @@ impl Handler<AddPeer> for Site {
 pub struct FileGetRequest {
 	#[serde(default)]
 	pub inner_path: String,
-	#[serde(default)]
+	#[serde(default = "default_required")]
 	pub required: bool,
 	#[serde(default)]
 	pub format: String,
@@ -274,15 +2328,35 @@ pub struct FileGetRequest {
 	pub timeout: f64,
 }
 
+fn default_required() -> bool {
+	true
+}
+
 impl Message for FileGetRequest {
 	type Result = Result<bool, Error>;
 }
 
 impl Handler<FileGetRequest> for Site {
-	type Result = Result<bool, Error>;
+	type Result = ResponseActFuture<Self, Result<bool, Error>>;
 
-	fn handle(&mut self, msg: FileGetRequest, _ctx: &mut Context<Self>) -> Self::Result {
-		self.need_file(&msg.inner_path)
+	// Required files are waited for, others are only queued
+	fn handle(&mut self, msg: FileGetRequest, ctx: &mut Context<Self>) -> Self::Result {
+		let result = self.need_file(ctx, &msg.inner_path);
+		let waiter = match result {
+			Ok(false) if msg.required => self.queue.wait(&msg.inner_path),
+			_ => None,
+		};
+		self.run_workers(ctx);
+		let download = async move {
+			match waiter {
+				Some(waiter) => match waiter.await {
+					Ok(true) => Ok(true),
+					_ => Err(Error::MissingError),
+				},
+				None => result,
+			}
+		};
+		Box::pin(download.into_actor(self))
 	}
 }
 
@@ -305,7 +2379,8 @@ impl Message for ChannelJoinRequest {
 impl Handler<ChannelJoinRequest> for Site {
 	type Result = Result<bool, Error>;
 
-	fn handle(&mut self, msg: ChannelJoinRequest, _ctx: &mut Context<Self>) -> Self::Result {
+	fn handle(&mut self, msg: ChannelJoinRequest, ctx: &mut Context<Self>) -> Self::Result {
+		self.visit(ctx);
 		let ws_addr = msg.ws_addr.clone();
 		if !self.listeners.contains(&ws_addr) {
 			self.listeners.push(ws_addr);
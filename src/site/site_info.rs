@@ -1,32 +1,16 @@
+use crate::util::is_default;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use uuid::Uuid;
 use zerucontent::Content;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-pub struct SiteFile {
-	added: usize,
-	ajax_key: String,
-	auth_key: String,
-	bytes_recv: usize,
-	bytes_sent: usize,
-	cache: SiteCache,
-	downloaded: usize,
-	modified: usize,
-	optional_downloaded: usize,
-	own: bool,
-	peers: usize,
-	permissions: Vec<String>,
-	serving: bool,
-	size: usize,
-	size_files_optional: usize,
-	size_optional: usize,
-	wrapper_key: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
 pub struct SiteCache {
-	bad_files: BTreeMap<String, usize>,
-	hashfield: String,
+	// Files that failed to download, with the number of failed tries
+	pub bad_files: BTreeMap<String, usize>,
+	pub hashfield: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -50,14 +34,57 @@ pub struct SiteInfo {
 	pub content_updated: f64,
 }
 
+// Settings of a site as stored in sites.json, in the format of the reference client
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
 pub struct SiteSettings {
-	pub peers: usize,
-	pub serving: bool,
+	// Timestamp of when the site was added
+	pub added: i64,
+	pub ajax_key: String,
+	// Keys are left out when sent to the site itself
+	#[serde(skip_serializing_if = "is_default")]
+	pub auth_key: String,
+	pub cache: SiteCache,
+	// Timestamp of when the site was first downloaded
+	pub downloaded: i64,
 	pub modified: f64,
 	pub own: bool,
+	pub peers: usize,
 	pub permissions: Vec<String>,
+	pub serving: bool,
+	// Bytes taken up by the site's files
 	pub size: usize,
+	// Size limit in MB approved by the user, 0 if the default applies
+	#[serde(skip_serializing_if = "is_default")]
+	pub size_limit: usize,
+	#[serde(skip_serializing_if = "is_default")]
+	pub wrapper_key: String,
+	// Settings of the reference client we don't use, kept as they are
+	#[serde(flatten)]
+	pub other: BTreeMap<String, serde_json::Value>,
+}
+
+impl SiteSettings {
+	// Settings of a site added just now
+	pub fn new() -> SiteSettings {
+		SiteSettings {
+			added: Utc::now().timestamp(),
+			ajax_key: random_key(),
+			auth_key: random_key(),
+			serving: true,
+			wrapper_key: random_key(),
+			..SiteSettings::default()
+		}
+	}
+}
+
+// Random key of 64 hex characters, like the ones of the reference client
+fn random_key() -> String {
+	format!(
+		"{}{}",
+		Uuid::new_v4().to_simple(),
+		Uuid::new_v4().to_simple()
+	)
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
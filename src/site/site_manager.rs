@@ -1,27 +1,53 @@
-use super::{address::Address, site_info::SiteInfo, Site};
+use super::{
+	address::Address,
+	site_info::{SiteInfo, SiteSettings},
+	site_storage::{SiteStorage, VerifyReport},
+	Site, SiteDelete, SiteSetServing, SiteSettingsRequest, SiteVerify,
+};
 use crate::error::Error;
 use crate::peer::Peer;
 use actix::{prelude::*, Actor, Addr};
 use chrono::{DateTime, Utc};
 use log::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
 
 use crate::environment::Environment;
 use crate::server::websocket::ZeruWebsocket;
+use crate::tracker::TrackerManager;
+use crate::util::atomic_write;
 use futures::executor::block_on;
 use futures::future::join_all;
-use futures::future::{FutureExt, TryFutureExt};
+use futures::future::FutureExt;
 use std::path::PathBuf;
-use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::mpsc::{channel, RecvError};
 
+// How often the settings of all sites are written to sites.json
+const SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
 pub fn start_site_manager(env: &Environment) -> Result<Addr<SiteManager>, RecvError> {
 	info!("Starting site manager.");
 
 	let data_path = env.data_path.clone();
+	let connected_limit = env.connected_limit;
+	let publish_limit = env.publish_limit;
+	let size_limit = env.size_limit;
+	let file_size_limit = env.file_size_limit;
+	let trackers = env.trackers.clone();
 	let (sender, receiver) = channel();
 	std::thread::spawn(move || {
-		let site_manager = SiteManager::new(data_path);
+		// Nothing is downloading yet, so temp files are left over from before
+		super::clean_temp_files(&data_path);
+		let mut site_manager = SiteManager::new(data_path);
+		site_manager.connected_limit = connected_limit;
+		site_manager.publish_limit = publish_limit;
+		site_manager.size_limit = size_limit;
+		site_manager.file_size_limit = file_size_limit;
+		site_manager.trackers = trackers;
+		if let Err(err) = site_manager.load() {
+			warn!("Could not load sites.json: {:?}", err);
+		}
 		let site_manager_system = System::new("Site manager");
 		let site_manager_addr = site_manager.start();
 		if sender.send(site_manager_addr).is_err() {
@@ -35,12 +61,50 @@ pub fn start_site_manager(env: &Environment) -> Result<Addr<SiteManager>, RecvEr
 	receiver.recv()
 }
 
+// Verify the files of a site without starting it, for use from the command line.
+// The bad files found are saved in sites.json, so they're downloaded once it runs
+pub fn verify_site(
+	env: &Environment,
+	address: Address,
+	delete_unlisted: bool,
+) -> Result<VerifyReport, Error> {
+	let mut site_manager = SiteManager::new(env.data_path.clone());
+	site_manager.load()?;
+	let settings = match site_manager.settings.get_mut(&address.to_string()) {
+		Some(settings) => settings,
+		None => return Err(Error::MissingError),
+	};
+	let mut site = Site::new(vec![], address, env.data_path.clone());
+	site.file_size_limit = env.file_size_limit;
+	site.settings = settings.clone();
+	let report = site.update_bad_files(delete_unlisted)?;
+	*settings = site.settings;
+	site_manager.save()?;
+	Ok(report)
+}
+
 pub struct SiteManager {
 	sites: HashMap<Address, Addr<Site>>,
 	nonce: HashMap<String, Address>,
 	updated_at: DateTime<Utc>,
 	listeners: Vec<Addr<ZeruWebsocket>>,
 	data_path: PathBuf,
+	// Maximum number of peers each site connects to
+	connected_limit: usize,
+	// Number of peers each site is published to
+	publish_limit: usize,
+	// Default size limit of each site in MB
+	size_limit: usize,
+	// Maximum size of a content.json file in MB
+	file_size_limit: usize,
+	// Settings of all sites by address, as last saved to sites.json
+	settings: BTreeMap<String, SiteSettings>,
+	// Trackers our sites are announced to
+	trackers: Vec<String>,
+	// Connected to the trackers on the first announce
+	tracker_manager: Option<TrackerManager>,
+	// Our own address, handed to the sites to announce through us
+	addr: Option<Addr<SiteManager>>,
 }
 
 impl SiteManager {
@@ -51,6 +115,14 @@ impl SiteManager {
 			updated_at: Utc::now(),
 			listeners: Vec::new(),
 			data_path,
+			connected_limit: super::CONNECTED_LIMIT,
+			publish_limit: super::PUBLISH_LIMIT,
+			size_limit: super::SIZE_LIMIT,
+			file_size_limit: super::FILE_SIZE_LIMIT,
+			settings: BTreeMap::new(),
+			trackers: Vec::new(),
+			tracker_manager: None,
+			addr: None,
 		}
 	}
 	pub fn get(&mut self, address: Address) -> Result<(Address, Addr<Site>), Error> {
@@ -61,11 +133,27 @@ impl SiteManager {
 				"Spinning up actor for site zero://{}",
 				address.get_address_short()
 			);
-			let site = Site::new(
+			let mut site = Site::new(
 				self.listeners.clone(),
 				address.clone(),
 				self.data_path.clone(),
 			);
+			site.connected_limit = self.connected_limit;
+			site.publish_limit = self.publish_limit;
+			site.size_limit = self.size_limit;
+			site.file_size_limit = self.file_size_limit;
+			site.site_manager = self.addr.clone();
+			// Sites only looked up get settings in sites.json once they're downloaded
+			site.settings = self
+				.settings
+				.get(&address.to_string())
+				.cloned()
+				.unwrap_or_else(SiteSettings::new);
+			if !site.settings.wrapper_key.is_empty() {
+				self
+					.nonce
+					.insert(site.settings.wrapper_key.clone(), address.clone());
+			}
 			let (sender, receiver) = channel();
 			std::thread::spawn(move || {
 				let site_system = System::new("Site system");
@@ -95,43 +183,117 @@ impl SiteManager {
 		error!("No site found for key {}", key);
 		Err(Error::MissingError)
 	}
-	pub fn write_to_file(&mut self) -> Pin<Box<Future<Output = ()>>> {
-		// TODO: remove this temp test:
+	/// Load the settings of all sites from data/sites.json
+	fn load(&mut self) -> Result<(), Error> {
+		let path = self.data_path.join("sites.json");
+		if !path.is_file() {
+			return Ok(());
+		}
+		let file = File::open(&path)?;
+		match serde_json::from_reader(std::io::BufReader::new(file)) {
+			Ok(settings) => self.settings = settings,
+			Err(err) => {
+				// Keep the unreadable file around instead of overwriting
+				// it with only the sites started since
+				let bad_path = self.data_path.join("sites.json-bad");
+				std::fs::rename(&path, &bad_path)?;
+				error!("Moved unreadable sites.json to {:?}", bad_path);
+				return Err(err.into());
+			}
+		}
+		info!("Loaded settings of {} sites", self.settings.len());
+		Ok(())
+	}
+	/// Save the settings of all sites to data/sites.json
+	fn save(&self) -> Result<(), Error> {
+		let buf = serde_json::to_vec_pretty(&self.settings)?;
+		atomic_write(&self.data_path.join("sites.json"), &buf)?;
+		Ok(())
+	}
+	// Start the actors of the sites in sites.json and announce the ones we serve
+	fn start_sites(&mut self) {
+		let addresses: Vec<Address> = self
+			.settings
+			.keys()
+			.filter_map(|address| match Address::from_str(address) {
+				Ok(address) => Some(address),
+				Err(_) => {
+					error!("Invalid site address {} in sites.json", address);
+					None
+				}
+			})
+			.collect();
+		for address in addresses {
+			if let Err(err) = self.get(address.clone()) {
+				error!("Could not start site {}: {:?}", address, err);
+				continue;
+			}
+			if self.is_serving(&address) && self.tracker_manager().announce(&address).is_err() {
+				trace!("Could not announce {} to all trackers", address);
+			}
+		}
+		info!("Started {} sites", self.sites.len());
+	}
+	// Sites without settings yet are new, so they're served
+	fn is_serving(&self, address: &Address) -> bool {
+		self
+			.settings
+			.get(&address.to_string())
+			.map_or(true, |settings| settings.serving)
+	}
+	fn tracker_manager(&mut self) -> &mut TrackerManager {
+		let trackers = &self.trackers;
+		self.tracker_manager.get_or_insert_with(|| {
+			let mut tracker_manager = TrackerManager::new();
+			for tracker in trackers {
+				if tracker_manager.add_tracker(tracker).is_err() {
+					error!("Could not add tracker {}", tracker);
+				}
+			}
+			tracker_manager
+		})
+	}
+	// Collect the settings of all running sites and write them to file
+	pub fn write_to_file(&mut self, ctx: &mut Context<Self>) {
 		let requests: Vec<_> = self
 			.sites
-			.values()
-			.map(|addr| {
+			.iter()
+			.map(|(address, addr)| {
+				let address = address.to_string();
 				addr
-					.send(super::SiteInfoRequest {})
-					.map_err(|err| error!("Site info request failed"))
+					.send(SiteSettingsRequest {})
+					.map(move |result| (address, result))
 			})
 			.collect();
 		let request = join_all(requests)
-			.map(|results| {
-				let mut site_infos = HashMap::new();
-				for info in results {
-					match info {
-						Ok(Ok(i)) => {
-							site_infos.insert(i.address.clone(), i);
+			.into_actor(self)
+			.map(|results, act, _ctx| {
+				for (address, result) in results {
+					match result {
+						Ok(Ok(settings)) => {
+							if settings.downloaded > 0 || act.settings.contains_key(&address) {
+								act.settings.insert(address, settings);
+							}
 						}
-						_ => return Err(()),
+						_ => error!("Could not collect settings of {}", address),
 					}
 				}
-				Ok(site_infos)
-			})
-			.map(|result| match result {
-				Ok(infos) => {
-					trace!("All SiteInfo: {:?}", infos);
+				if let Err(err) = act.save() {
+					error!("Error writing sites.json: {:?}", err);
 				}
-				Err(err) => error!("Error encountered collecting site information"),
 			});
-		// TODO: actually write the resulting structure to a file
-		return Box::pin(request);
+		ctx.spawn(request);
 	}
 }
 
 impl Actor for SiteManager {
 	type Context = Context<Self>;
+
+	fn started(&mut self, ctx: &mut Context<Self>) {
+		self.addr = Some(ctx.address());
+		self.start_sites();
+		ctx.run_interval(SAVE_INTERVAL, |act, ctx| act.write_to_file(ctx));
+	}
 }
 
 #[derive(Debug)]
@@ -155,6 +317,170 @@ impl Handler<Lookup> for SiteManager {
 	}
 }
 
+/// Message struct used to add a site we already have the files of,
+/// like one cloned or created by the user
+pub struct AddSite {
+	pub address: Address,
+	pub own: bool,
+}
+
+impl Message for AddSite {
+	type Result = Result<Addr<Site>, Error>;
+}
+
+impl Handler<AddSite> for SiteManager {
+	type Result = Result<Addr<Site>, Error>;
+
+	fn handle(&mut self, msg: AddSite, ctx: &mut Context<Self>) -> Self::Result {
+		let settings = self
+			.settings
+			.entry(msg.address.to_string())
+			.or_insert_with(SiteSettings::new);
+		settings.own = msg.own;
+		settings.serving = true;
+		let (_, addr) = self.get(msg.address)?;
+		self.write_to_file(ctx);
+		Ok(addr)
+	}
+}
+
+/// Message struct used to delete a site, stopping its actor and removing
+/// its files, its content.db rows and its entry in sites.json
+pub struct DeleteSite {
+	pub address: Address,
+}
+
+impl Message for DeleteSite {
+	type Result = Result<(), Error>;
+}
+
+impl Handler<DeleteSite> for SiteManager {
+	type Result = Result<(), Error>;
+
+	fn handle(&mut self, msg: DeleteSite, _ctx: &mut Context<Self>) -> Self::Result {
+		let address = msg.address;
+		let key = address.to_string();
+		if !self.sites.contains_key(&address) && !self.settings.contains_key(&key) {
+			return Err(Error::MissingError);
+		}
+		info!("Deleting site zero://{}", address.get_address_short());
+		match self.sites.remove(&address) {
+			Some(addr) => match block_on(addr.send(SiteDelete {})) {
+				Ok(result) => result?,
+				Err(_) => return Err(Error::MailboxError),
+			},
+			None => super::remove_site_files(&self.data_path, &address)?,
+		}
+		self.nonce.retain(|_, site| *site != address);
+		self.settings.remove(&key);
+		self.updated_at = Utc::now();
+		if let Err(err) = self.save() {
+			error!("Error writing sites.json: {:?}", err);
+		}
+		let db_path = self.data_path.join("content.db");
+		if db_path.is_file() {
+			match content_manager::ContentManager::new(&db_path.to_string_lossy()) {
+				Ok(db) => {
+					if let Err(err) = db.remove_site(&key) {
+						error!("Could not remove {} from content.db: {:?}", key, err);
+					}
+				}
+				Err(err) => error!("Could not open content.db: {:?}", err),
+			}
+		}
+		let sites: Vec<Address> = self.sites.keys().cloned().collect();
+		if self.tracker_manager().announce_delete(&sites).is_err() {
+			warn!("Could not announce the deletion of {} to all trackers", key);
+		}
+		Ok(())
+	}
+}
+
+/// Message struct used to pause or resume a site, the serving state is persisted in sites.json
+pub struct SetServing {
+	pub address: Address,
+	pub serving: bool,
+}
+
+impl Message for SetServing {
+	type Result = Result<(), Error>;
+}
+
+impl Handler<SetServing> for SiteManager {
+	type Result = Result<(), Error>;
+
+	fn handle(&mut self, msg: SetServing, ctx: &mut Context<Self>) -> Self::Result {
+		let key = msg.address.to_string();
+		if !self.sites.contains_key(&msg.address) && !self.settings.contains_key(&key) {
+			return Err(Error::MissingError);
+		}
+		let (_, addr) = self.get(msg.address)?;
+		match block_on(addr.send(SiteSetServing {
+			serving: msg.serving,
+		})) {
+			Ok(result) => result?,
+			Err(_) => return Err(Error::MailboxError),
+		}
+		if let Some(settings) = self.settings.get_mut(&key) {
+			settings.serving = msg.serving;
+		}
+		self.updated_at = Utc::now();
+		self.write_to_file(ctx);
+		Ok(())
+	}
+}
+
+/// Message struct used by sites to announce themselves to the trackers
+/// whenever they announce to their peers
+pub struct AnnounceSite {
+	pub address: Address,
+}
+
+impl Message for AnnounceSite {
+	type Result = ();
+}
+
+impl Handler<AnnounceSite> for SiteManager {
+	type Result = ();
+
+	fn handle(&mut self, msg: AnnounceSite, _ctx: &mut Context<Self>) -> Self::Result {
+		if self.is_serving(&msg.address) && self.tracker_manager().announce(&msg.address).is_err() {
+			trace!("Could not announce {} to all trackers", msg.address);
+		}
+	}
+}
+
+/// Message struct used to verify the files of a site, the bad files found
+/// are persisted in sites.json until they're downloaded again
+pub struct VerifySite {
+	pub address: Address,
+	pub delete_unlisted: bool,
+}
+
+impl Message for VerifySite {
+	type Result = Result<VerifyReport, Error>;
+}
+
+impl Handler<VerifySite> for SiteManager {
+	type Result = Result<VerifyReport, Error>;
+
+	fn handle(&mut self, msg: VerifySite, ctx: &mut Context<Self>) -> Self::Result {
+		let key = msg.address.to_string();
+		if !self.sites.contains_key(&msg.address) && !self.settings.contains_key(&key) {
+			return Err(Error::MissingError);
+		}
+		let (_, addr) = self.get(msg.address)?;
+		let report = match block_on(addr.send(SiteVerify {
+			delete_unlisted: msg.delete_unlisted,
+		})) {
+			Ok(result) => result?,
+			Err(_) => return Err(Error::MailboxError),
+		};
+		self.write_to_file(ctx);
+		Ok(report)
+	}
+}
+
 pub struct SitesChangedRequest {}
 
 impl Message for SitesChangedRequest {
@@ -183,12 +509,29 @@ impl Handler<SiteListRequest> for SiteManager {
 			self
 				.sites
 				.iter()
+				.filter(|(key, _)| self.is_serving(key))
 				.map(|(key, _)| serde_bytes::ByteBuf::from(key.get_address_hash()))
 				.collect(),
 		)
 	}
 }
 
+pub struct SiteServedRequest {
+	pub address: Address,
+}
+
+impl Message for SiteServedRequest {
+	type Result = Result<bool, Error>;
+}
+
+impl Handler<SiteServedRequest> for SiteManager {
+	type Result = Result<bool, Error>;
+
+	fn handle(&mut self, msg: SiteServedRequest, _ctx: &mut Context<Self>) -> Self::Result {
+		Ok(self.sites.contains_key(&msg.address) && self.is_serving(&msg.address))
+	}
+}
+
 pub struct SiteInfoListRequest {}
 
 impl Message for SiteInfoListRequest {
@@ -199,9 +542,7 @@ impl Handler<SiteInfoListRequest> for SiteManager {
 	type Result = ResponseActFuture<Self, Result<Vec<SiteInfo>, Error>>;
 
 	fn handle(&mut self, _msg: SiteInfoListRequest, ctx: &mut Context<Self>) -> Self::Result {
-		// TODO: Decide when the sites should be written to file
-		let fu = self.write_to_file();
-		block_on(fu);
+		self.write_to_file(ctx);
 
 		let requests: Vec<_> = self
 			.sites
@@ -277,6 +618,7 @@ impl Handler<AddWrapperKey> for SiteManager {
 pub struct AddPeer {
 	pub peer_id: String,
 	pub peer_addr: Addr<Peer>,
+	pub address: String,
 	pub sites: Vec<Vec<u8>>,
 }
 
@@ -295,6 +637,7 @@ impl Handler<AddPeer> for SiteManager {
 					addr.do_send(crate::site::AddPeer {
 						peer_id: msg.peer_id.clone(),
 						peer_addr: msg.peer_addr.clone(),
+						address: msg.address.clone(),
 					})
 				}
 			}
@@ -1,4 +1,39 @@
-use super::Site;
+use super::content_rules::matches_start;
+use super::file_request::{FileRequest, Priority};
+use super::{content_dir, dir_size, file_hash, Site};
+use crate::error::Error;
+use crate::optional_files::hashfield::hash_id;
+use crate::util::is_valid_inner_path;
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+use zerucontent::Content;
+
+/// Result of verifying the files of a site against its content.json files,
+/// with paths relative to the site directory
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct VerifyReport {
+	// Number of files checked, optional files we don't have aren't
+	pub checked: usize,
+	// Listed files we don't have
+	pub missing: Vec<String>,
+	// Files that don't match their listed size and hash,
+	// content.json files that aren't valid
+	pub corrupt: Vec<String>,
+	// Files that no content.json file lists
+	pub unlisted: Vec<String>,
+	// Number of bad files queued to be downloaded again
+	pub queued: usize,
+	// Number of unlisted files deleted
+	pub deleted: usize,
+}
+
+impl VerifyReport {
+	pub fn is_ok(&self) -> bool {
+		self.missing.is_empty() && self.corrupt.is_empty()
+	}
+}
 
 pub trait SiteStorage {
 	fn get_db_file() {}
@@ -8,7 +43,7 @@ pub trait SiteStorage {
 	fn load_db() {}
 	fn get_db() {}
 	fn update_db_file() {}
-	fn get_db_files() {}
+	fn get_db_files(&self) -> Vec<String>;
 	fn rebuild_db() {}
 	fn query() {}
 	fn ensure_dir() {}
@@ -30,9 +65,288 @@ pub trait SiteStorage {
 	fn is_dir() {}
 	fn get_path() {}
 	fn get_inner_path() {}
-	fn verify_files() {}
-	fn update_bad_files() {}
+	fn verify_files(&self) -> Result<VerifyReport, Error>;
+	fn update_bad_files(&mut self, delete_unlisted: bool) -> Result<VerifyReport, Error>;
 	fn delete_files() {}
 }
 
-impl SiteStorage for Site {}
+impl SiteStorage for Site {
+	// Json files to import into the site database, the content.json files and
+	// the json files they list, leaving out the content of archived users
+	fn get_db_files(&self) -> Vec<String> {
+		let root = match &self.content {
+			Some(content) => content,
+			None => return vec![],
+		};
+		let site_path = self.site_path();
+		let contents = std::iter::once(("content.json", root))
+			.chain(self.contents.iter().map(|(p, c)| (p.as_str(), c)));
+		let mut paths = vec![];
+		for (content_path, content) in contents {
+			if !self.is_archived(content_path, content.modified) {
+				paths.extend(json_files(content_path, content));
+			}
+		}
+		paths.retain(|path| is_valid_inner_path(path) && site_path.join(path).is_file());
+		paths
+	}
+	// Check the size and hash of every file listed in the content.json files we have,
+	// along with the signatures of the content.json files themselves
+	fn verify_files(&self) -> Result<VerifyReport, Error> {
+		let root = self.content.as_ref().ok_or(Error::MissingError)?;
+		let site_path = self.site_path();
+		let mut report = VerifyReport::default();
+		let mut listed = BTreeSet::new();
+		let contents = std::iter::once(("content.json", root))
+			.chain(self.contents.iter().map(|(p, c)| (p.as_str(), c)));
+		for (content_path, content) in contents {
+			listed.insert(String::from(content_path));
+			report.checked += 1;
+			match site_path.join(content_path).metadata() {
+				Ok(m) if self.verify_content(content_path, content, m.len() as usize) => {}
+				Ok(_) => report.corrupt.push(String::from(content_path)),
+				Err(_) => report.missing.push(String::from(content_path)),
+			}
+			let dir = content_dir(content_path);
+			for path in content.includes.keys() {
+				let inner_path = format!("{}{}", dir, path);
+				if self.get_content(&inner_path).is_none() && is_valid_inner_path(&inner_path) {
+					listed.insert(inner_path.clone());
+					report.checked += 1;
+					report.missing.push(inner_path);
+				}
+			}
+			let files = content
+				.files
+				.iter()
+				.map(|file| (file, false))
+				.chain(content.files_optional.iter().map(|file| (file, true)));
+			for ((path, file), optional) in files {
+				let inner_path = format!("{}{}", dir, path);
+				if !is_valid_inner_path(&inner_path) {
+					continue;
+				}
+				let path = site_path.join(&inner_path);
+				listed.insert(inner_path.clone());
+				let size = match path.metadata() {
+					Ok(m) => m.len() as usize,
+					// Optional files are only checked if we have them
+					Err(_) if optional => continue,
+					Err(_) => {
+						report.checked += 1;
+						report.missing.push(inner_path);
+						continue;
+					}
+				};
+				report.checked += 1;
+				if size != file.size || file_hash(&path).as_ref() != Some(&file.sha512) {
+					report.corrupt.push(inner_path);
+				}
+			}
+		}
+		let ignored = ignored_paths(&site_path);
+		let mut paths = vec![];
+		walk_files(&site_path, "", &mut paths);
+		report.unlisted = paths
+			.into_iter()
+			.filter(|inner_path| !listed.contains(inner_path))
+			.filter(|inner_path| {
+				let name = inner_path.rsplit('/').next().unwrap_or("");
+				!name.starts_with('.') && !name.ends_with("-old") && !name.ends_with("-new")
+			})
+			.filter(|inner_path| !ignored.iter().any(|path| inner_path.starts_with(path)))
+			.filter(|inner_path| !self.is_ignored(inner_path))
+			.collect();
+		Ok(report)
+	}
+	// Verify the files of the site and queue the missing and corrupt ones to be
+	// downloaded again, corrupt optional files are removed instead
+	fn update_bad_files(&mut self, delete_unlisted: bool) -> Result<VerifyReport, Error> {
+		if self.content.is_none() {
+			self.load_contents();
+		}
+		let mut report = self.verify_files()?;
+		let site_path = self.site_path();
+		let bad_files: Vec<String> = report
+			.missing
+			.iter()
+			.chain(report.corrupt.iter())
+			.cloned()
+			.collect();
+		for inner_path in bad_files.iter() {
+			let file = self.need_file_info(inner_path);
+			if self.is_optional(inner_path) {
+				if let Some(hash_id) = file.as_ref().and_then(|f| hash_id(&f.sha512)) {
+					if self.hashfield.remove(hash_id) {
+						self.hashfield_changed = true;
+					}
+				}
+				if let Err(err) = std::fs::remove_file(site_path.join(inner_path)) {
+					error!(
+						"Could not remove corrupt optional file {}: {:?}",
+						inner_path, err
+					);
+				}
+				continue;
+			}
+			self
+				.settings
+				.cache
+				.bad_files
+				.entry(inner_path.clone())
+				.or_insert(0);
+			let priority =
+				Priority::from_path(Path::new(inner_path), false).unwrap_or(Priority::Required);
+			if self.queue.add(FileRequest::new(inner_path, priority, file)) {
+				report.queued += 1;
+			}
+		}
+		if delete_unlisted {
+			for inner_path in report.unlisted.iter() {
+				match std::fs::remove_file(site_path.join(inner_path)) {
+					Ok(()) => report.deleted += 1,
+					Err(err) => error!("Could not remove unlisted file {}: {:?}", inner_path, err),
+				}
+			}
+		}
+		self.settings.size = dir_size(&site_path);
+		info!(
+			"Verified {} files of {}: {} missing, {} corrupt, {} unlisted",
+			report.checked,
+			self.address.get_address_short(),
+			report.missing.len(),
+			report.corrupt.len(),
+			report.unlisted.len()
+		);
+		Ok(report)
+	}
+}
+
+impl Site {
+	// Whether a file is listed as optional by any of our content.json files
+	fn is_optional(&self, inner_path: &str) -> bool {
+		std::iter::once(("content.json", self.content.as_ref()))
+			.chain(self.contents.iter().map(|(p, c)| (p.as_str(), Some(c))))
+			.any(|(content_path, content)| {
+				match (content, inner_path.strip_prefix(content_dir(content_path))) {
+					(Some(content), Some(path)) => content.files_optional.contains_key(path),
+					_ => false,
+				}
+			})
+	}
+	// Whether a file is left out of signing by the ignore pattern of a content.json
+	// file in one of its parent directories
+	fn is_ignored(&self, inner_path: &str) -> bool {
+		std::iter::once(("content.json", self.content.as_ref()))
+			.chain(self.contents.iter().map(|(p, c)| (p.as_str(), Some(c))))
+			.any(|(content_path, content)| {
+				let ignore = match content.and_then(|c| c.ignore.as_ref()) {
+					Some(ignore) => ignore,
+					None => return false,
+				};
+				match inner_path.strip_prefix(content_dir(content_path)) {
+					Some(path) => matches_start(ignore, path),
+					None => false,
+				}
+			})
+	}
+}
+
+// Files the site creates itself, like the database described in dbschema.json
+fn ignored_paths(site_path: &Path) -> Vec<String> {
+	let schema: Option<serde_json::Value> = std::fs::read(site_path.join("dbschema.json"))
+		.ok()
+		.and_then(|buf| serde_json::from_slice(&buf).ok());
+	schema
+		.as_ref()
+		.and_then(|schema| schema.get("db_file"))
+		.and_then(|db_file| db_file.as_str())
+		.map(|db_file| vec![String::from(db_file)])
+		.unwrap_or_default()
+}
+
+// Inner paths of a content.json file and the json files it lists
+fn json_files(content_path: &str, content: &Content) -> Vec<String> {
+	let dir = content_dir(content_path);
+	let listed = content
+		.files
+		.keys()
+		.chain(content.files_optional.keys())
+		.filter(|path| path.ends_with(".json"))
+		.map(|path| format!("{}{}", dir, path));
+	std::iter::once(String::from(content_path))
+		.chain(listed)
+		.collect()
+}
+
+// Collect the inner paths of all files below dir
+fn walk_files(dir: &Path, prefix: &str, paths: &mut Vec<String>) {
+	let entries = match std::fs::read_dir(dir) {
+		Ok(e) => e,
+		Err(_) => return,
+	};
+	for entry in entries.filter_map(|e| e.ok()) {
+		let name = entry.file_name().to_string_lossy().to_string();
+		let path = entry.path();
+		if path.is_dir() {
+			walk_files(&path, &format!("{}{}/", prefix, name), paths);
+		} else {
+			paths.push(format!("{}{}", prefix, name));
+		}
+	}
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_json_files() {
+		let content: Content = serde_json::from_str(
+			r#"{
+				"files": {
+					"data.json": {"sha512": "", "size": 2},
+					"img/avatar.png": {"sha512": "", "size": 10}
+				},
+				"files_optional": {"data/old.json": {"sha512": "", "size": 2}}
+			}"#,
+		)
+		.unwrap();
+		assert_eq!(
+			json_files("data/users/1User/content.json", &content),
+			vec![
+				"data/users/1User/content.json",
+				"data/users/1User/data.json",
+				"data/users/1User/data/old.json",
+			]
+		);
+		assert_eq!(
+			json_files("content.json", &Content::default()),
+			vec!["content.json"]
+		);
+	}
+
+	#[test]
+	fn test_walk_files() {
+		let dir = std::env::temp_dir().join(format!("walk-files-{}", std::process::id()));
+		std::fs::create_dir_all(dir.join("data/users")).unwrap();
+		std::fs::write(dir.join("content.json"), b"{}").unwrap();
+		std::fs::write(
+			dir.join("dbschema.json"),
+			b"{\"db_file\": \"data/site.db\"}",
+		)
+		.unwrap();
+		std::fs::write(dir.join("data/users/content.json"), b"{}").unwrap();
+		let mut paths = vec![];
+		walk_files(&dir, "", &mut paths);
+		paths.sort();
+		assert_eq!(
+			paths,
+			vec!["content.json", "data/users/content.json", "dbschema.json"]
+		);
+		assert_eq!(ignored_paths(&dir), vec![String::from("data/site.db")]);
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert!(ignored_paths(&dir).is_empty());
+	}
+}
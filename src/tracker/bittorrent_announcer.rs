@@ -8,6 +8,11 @@ impl Announcer for BittorrentAnnouncer {
 	fn announce(&mut self, address: &Address) -> Result<(), ()> {
 		warn!("Bittorrent announcer is not implemented");
 
+		Ok(())
+	}
+	fn announce_delete(&mut self, sites: &[Address]) -> Result<(), ()> {
+		warn!("Bittorrent announcer is not implemented");
+
 		Ok(())
 	}
 }
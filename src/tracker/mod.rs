@@ -35,6 +35,8 @@ use zero_announcer::ZeroAnnouncer;
 
 pub trait Announcer {
 	fn announce(&mut self, site: &Address) -> Result<(), ()>;
+	// Announce all sites we serve, the tracker forgets us for the ones left out
+	fn announce_delete(&mut self, sites: &[Address]) -> Result<(), ()>;
 }
 
 // pub trait Tracker {
@@ -132,6 +134,13 @@ impl TrackerManager {
 		}
 		Ok(())
 	}
+	// Let trackers know we no longer serve the sites left out of `sites`
+	pub fn announce_delete(&mut self, sites: &[Address]) -> Result<(), ()> {
+		for tracker in self.trackers.iter_mut() {
+			tracker.announce_delete(sites)?;
+		}
+		Ok(())
+	}
 }
 
 // pub struct SiteStats {}
@@ -55,13 +55,16 @@ fn site_announce(addr: &Address) -> Announce {
 	};
 	Announce { req }
 }
-fn full_announce() -> Announce {
+fn full_announce(sites: &[Address]) -> Announce {
 	let req = templates::Announce {
-		port: 0,
-		add: vec![],
+		port: 11692,
+		add: vec!["ipv4".to_string()],
 		need_types: vec![],
-		need_num: 20,
-		hashes: vec![],
+		need_num: 0,
+		hashes: sites
+			.iter()
+			.map(|addr| serde_bytes::ByteBuf::from(addr.get_address_hash()))
+			.collect(),
 		onions: vec![],
 		onion_signs: vec![],
 		onion_sign_this: String::new(),
@@ -97,4 +100,14 @@ impl Announcer for ZeroAnnouncer {
 		trace!("Received announce response: {:?}", res);
 		Err(())
 	}
+	fn announce_delete(&mut self, sites: &[Address]) -> Result<(), ()> {
+		trace!("Sending full AnnounceRequest for {} sites...", sites.len());
+		match block_on(self.peer.send(full_announce(sites))) {
+			Ok(Ok(_)) => Ok(()),
+			res => {
+				trace!("Full announce failed: {:?}", res);
+				Err(())
+			}
+		}
+	}
 }
@@ -1,8 +1,16 @@
 pub mod user_manager;
 
+use crate::error::Error;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::util::bip32::{ChildNumber, ExtendedPrivKey};
+use bitcoin::{Address, Network};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
 
+// Site keys are derived with a BIP32 index below this, like the reference client
+const MAX_ADDRESS_INDEX: usize = 100_000_000;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct User {
 	#[serde(default)]
@@ -51,10 +59,24 @@ impl User {
 
 	fn set_site_settings() {}
 
-	/// Get data for a new, unique site
+	/// Get data for a new, unique site, the private key is derived from the
+	/// master seed with a random index, which is kept in the site's content.json
 	///
-	/// Return: [site_address, bip32_index, {"auth_address": "1AddR", "auth_privatekey": "xxx", "privatekey": "xxx"}]
-	fn get_new_site_data() {}
+	/// Return: (site_address, address_index, privatekey)
+	pub fn get_new_site_data(&mut self) -> Result<(String, usize, String), Error> {
+		if !self.sites.is_object() {
+			self.sites = json!({});
+		}
+		loop {
+			let address_index = rand::random::<u32>() as usize % MAX_ADDRESS_INDEX;
+			let (address, privatekey) = hd_privatekey(&self.master_seed, address_index)?;
+			if self.sites.get(&address).is_some() {
+				continue;
+			}
+			self.sites[&address] = json!({ "privatekey": privatekey });
+			return Ok((address, address_index, privatekey));
+		}
+	}
 	/// Get BIP32 address from site address
 	///
 	/// Return: BIP32 auth address
@@ -81,3 +103,40 @@ impl User {
 	/// Return user@certprovider.bit or None
 	fn get_cert_user_id() {}
 }
+
+/// Derive the key at the BIP32 index from the master seed, uncompressed like
+/// the keys of the reference client
+///
+/// Return: (address, privatekey)
+fn hd_privatekey(master_seed: &str, index: usize) -> Result<(String, String), Error> {
+	let derive = || -> Result<(String, String), bitcoin::util::bip32::Error> {
+		let secp = Secp256k1::new();
+		let master = ExtendedPrivKey::new_master(Network::Bitcoin, master_seed.as_bytes())?;
+		let child = ChildNumber::from_normal_idx((index % MAX_ADDRESS_INDEX) as u32)?;
+		let mut privatekey = master.ckd_priv(&secp, child)?.private_key;
+		privatekey.compressed = false;
+		let address = Address::p2pkh(&privatekey.public_key(&secp), Network::Bitcoin);
+		Ok((address.to_string(), privatekey.to_wif()))
+	};
+	derive().map_err(|err| Error::CryptError(err.to_string()))
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_new_site_data() {
+		let mut user = User::from_seed(String::from(
+			"024bbb3be5b6d1d2bf9a1a19d71f7a08a1f0de1b36e1c8e2ba1fe8d1d6a5d4a8",
+		));
+		let (address, address_index, privatekey) = user.get_new_site_data().unwrap();
+		assert!(address_index < MAX_ADDRESS_INDEX);
+		assert!(privatekey.starts_with('5'));
+		assert_eq!(user.sites[&address]["privatekey"], privatekey);
+		// The key can be recovered from the seed and index
+		let recovered = hd_privatekey(&user.master_seed, address_index).unwrap();
+		assert_eq!(recovered, (address, privatekey));
+	}
+}
@@ -1,6 +1,7 @@
 use super::User;
 use crate::environment::Environment;
 use crate::error::Error;
+use crate::util::private_write;
 use actix::{prelude::*, Actor, Addr};
 use log::*;
 use std::collections::HashMap;
@@ -80,12 +81,13 @@ impl UserManager {
 		self.users.get(master_address).cloned()
 	}
 
-	/// Save all users in memory to file
+	/// Save all users in memory to file, only readable by us
+	/// as it holds the master seeds and site private keys
 	fn save(&mut self) -> Result<(), Error> {
 		let mut path = self.data_path.clone();
 		path.push("users.json");
-		let file = File::open(path)?;
-		serde_json::to_writer_pretty(file, &self.users)?;
+		let buf = serde_json::to_vec_pretty(&self.users)?;
+		private_write(&path, &buf)?;
 
 		Ok(())
 	}
@@ -106,3 +108,34 @@ impl Handler<UserRequest> for UserManager {
 		self.get(&msg.address)
 	}
 }
+
+/// Message struct used to create the key of a new site for a user,
+/// returns the address, BIP32 index and private key of the site
+pub struct NewSiteRequest {
+	// Master address of the user, the first user if empty
+	pub address: String,
+}
+
+impl Message for NewSiteRequest {
+	type Result = Result<(String, usize, String), Error>;
+}
+
+impl Handler<NewSiteRequest> for UserManager {
+	type Result = Result<(String, usize, String), Error>;
+
+	fn handle(&mut self, msg: NewSiteRequest, _ctx: &mut Self::Context) -> Self::Result {
+		if msg.address.is_empty() && self.users.is_empty() {
+			self.create();
+		}
+		let user = match msg.address.as_str() {
+			"" => self.users.values_mut().next(),
+			address => self.users.get_mut(address),
+		};
+		let site_data = match user {
+			Some(user) => user.get_new_site_data()?,
+			None => return Err(Error::MissingError),
+		};
+		self.save()?;
+		Ok(site_data)
+	}
+}
@@ -1,5 +1,146 @@
+use sha2::Digest;
 use std::default::Default;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Component, Path};
 
 pub fn is_default<T: Default + PartialEq>(t: &T) -> bool {
 	t == &T::default()
 }
+
+// Inner paths may not be absolute or point outside of the site directory
+pub fn is_valid_inner_path(inner_path: &str) -> bool {
+	if inner_path.is_empty() {
+		return false;
+	}
+	Path::new(inner_path)
+		.components()
+		.all(|c| matches!(c, Component::Normal(_)))
+}
+
+// Write a file through a temp file next to it, so it's either
+// the old or the new version if we're interrupted along the way
+pub fn atomic_write(path: &Path, buf: &[u8]) -> std::io::Result<()> {
+	write_through_temp(path, buf, OpenOptions::new())
+}
+
+// Like atomic_write, but the file is only accessible by us,
+// for files holding private keys
+pub fn private_write(path: &Path, buf: &[u8]) -> std::io::Result<()> {
+	let mut options = OpenOptions::new();
+	#[cfg(unix)]
+	options.mode(0o600);
+	write_through_temp(path, buf, options)
+}
+
+fn write_through_temp(path: &Path, buf: &[u8], mut options: OpenOptions) -> std::io::Result<()> {
+	let mut temp_path = path.as_os_str().to_owned();
+	temp_path.push("-new");
+	let temp_path = Path::new(&temp_path);
+	// A temp file left behind could have other permissions
+	if temp_path.exists() {
+		std::fs::remove_file(temp_path)?;
+	}
+	let mut file = options.write(true).create_new(true).open(temp_path)?;
+	file.write_all(buf)?;
+	file.sync_all()?;
+	std::fs::rename(temp_path, path)
+}
+
+// Writer hashing everything written to the inner writer with sha512t,
+// the sha512 truncated to 64 hex characters as used in content.json
+pub struct HashWriter<W> {
+	inner: W,
+	hasher: sha2::Sha512,
+	written: usize,
+}
+
+impl<W: Write> HashWriter<W> {
+	pub fn new(inner: W) -> HashWriter<W> {
+		HashWriter {
+			inner,
+			hasher: sha2::Sha512::default(),
+			written: 0,
+		}
+	}
+	pub fn written(&self) -> usize {
+		self.written
+	}
+	// Returns the inner writer along with the hash of everything written to it
+	pub fn finish(self) -> (W, String) {
+		let mut hash = hex::encode(self.hasher.result());
+		hash.truncate(64);
+		(self.inner, hash)
+	}
+}
+
+impl<W: Write> Write for HashWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		let written = self.inner.write(buf)?;
+		self.hasher.input(&buf[..written]);
+		self.written += written;
+		Ok(written)
+	}
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+#[cfg(test)]
+#[cfg_attr(tarpaulin, ignore)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_inner_path_validation() {
+		assert_eq!(is_valid_inner_path("content.json"), true);
+		assert_eq!(is_valid_inner_path("data/users/content.json"), true);
+		assert_eq!(is_valid_inner_path(""), false);
+		assert_eq!(is_valid_inner_path("/etc/passwd"), false);
+		assert_eq!(is_valid_inner_path("../users.json"), false);
+		assert_eq!(is_valid_inner_path("data/../../users.json"), false);
+	}
+
+	#[test]
+	fn test_atomic_write() {
+		let path = std::env::temp_dir().join(format!("atomic-write-{}.json", std::process::id()));
+		atomic_write(&path, b"{}").unwrap();
+		atomic_write(&path, b"{\"sites\": 1}").unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), b"{\"sites\": 1}");
+		let mut temp_path = path.as_os_str().to_owned();
+		temp_path.push("-new");
+		assert!(!Path::new(&temp_path).exists());
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_private_write() {
+		use std::os::unix::fs::PermissionsExt;
+		let path = std::env::temp_dir().join(format!("private-write-{}.pem", std::process::id()));
+		std::fs::write(&path, b"old").unwrap();
+		private_write(&path, b"key").unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), b"key");
+		let mode = path.metadata().unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o600);
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn test_hash_writer() {
+		let (_, hash) = HashWriter::new(std::io::sink()).finish();
+		assert_eq!(
+			hash,
+			"cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce"
+		);
+		let mut chunked = HashWriter::new(Vec::new());
+		chunked.write_all(b"Hello ").unwrap();
+		chunked.write_all(b"ZeroNet").unwrap();
+		let mut whole = HashWriter::new(Vec::new());
+		whole.write_all(b"Hello ZeroNet").unwrap();
+		assert_eq!(chunked.written(), 13);
+		assert_eq!(chunked.finish(), whole.finish());
+	}
+}
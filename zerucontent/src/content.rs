@@ -58,6 +58,13 @@ pub struct Content {
 	pub favicon: String,
 	#[serde(skip_serializing_if = "is_default")]
 	pub user_contents: UserContents,
+	// Certificate of the user signing user content
+	#[serde(skip_serializing_if = "is_default")]
+	pub cert_auth_type: String,
+	#[serde(skip_serializing_if = "is_default")]
+	pub cert_user_id: String,
+	#[serde(skip_serializing_if = "is_default")]
+	pub cert_sign: String,
 	#[serde(skip_serializing_if = "is_default")]
 	pub viewport: String,
 	#[serde(skip_serializing_if = "is_default")]
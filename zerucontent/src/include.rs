@@ -6,13 +6,15 @@ use std::default::Default;
 #[derive(Serialize, Deserialize, Default, PartialEq, Clone)]
 #[serde(default)]
 pub struct Include {
-	signers: Vec<String>,
+	pub signers: Vec<String>,
 	#[serde(skip_serializing_if = "is_default")]
-	signers_required: u64,
+	pub signers_required: u64,
 	#[serde(skip_serializing_if = "is_default")]
-	files_allowed: String,
+	pub files_allowed: String,
 	#[serde(skip_serializing_if = "is_default")]
-	includes_allowed: bool,
+	pub files_allowed_optional: String,
 	#[serde(skip_serializing_if = "is_default")]
-	max_size: u64,
+	pub includes_allowed: bool,
+	#[serde(skip_serializing_if = "is_default")]
+	pub max_size: u64,
 }
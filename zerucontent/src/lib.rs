@@ -62,4 +62,36 @@ mod tests {
 		let result = content.verify(key);
 		assert_eq!(result, true)
 	}
+
+	#[test]
+	fn test_archived() {
+		let user_contents: UserContents = serde_json::from_str(
+			r#"{"archived": {"1BanUser": 1500000000}, "archived_before": 1400000000}"#,
+		)
+		.unwrap();
+		assert!(user_contents.is_archived("1BanUser", 1500000000.0));
+		assert!(!user_contents.is_archived("1BanUser", 1500000001.0));
+		assert!(user_contents.is_archived("1OtherUser", 1300000000.0));
+		assert!(!user_contents.is_archived("1OtherUser", 1450000000.0));
+		assert!(!UserContents::default().is_archived("1OtherUser", 0.0));
+		let json = serde_json::to_string(&UserContents::default()).unwrap();
+		assert_eq!(json, "{}");
+	}
+
+	#[test]
+	fn test_user_contents_round_trip() {
+		let signed = r#"{"address": "1TeSTvb4w2PWE81S2rEELgmX2GCCExQGT", "files": {}, "ignore": ".*", "inner_path": "data/users/content.json", "modified": 1500000000.5, "signs_required": 1, "user_contents": {"cert_signers": {"zeroid.bit": ["1iD5ZQJMNXu43w1qLB8sfdHVKppVMduGz"]}, "permission_rules": {".*": {"files_allowed": "data.json", "max_size": 10000}}, "permissions": {"banned@zeroid.bit": false}}, "zeronet_version": "0.7.1"}"#;
+		let key = String::from("1TeSTvb4w2PWE81S2rEELgmX2GCCExQGT");
+		let signature = zerucrypt::sign(
+			signed.as_bytes(),
+			"5KUH8PHKcNiZrQJRDpBkPiEaZuFtmZgnuaPvdYE3KAmiuJ8hQTC",
+		)
+		.unwrap();
+		let mut content: Content = serde_json::from_str(signed).unwrap();
+		assert_eq!(content.dump().unwrap(), signed);
+		content.signs.insert(key.clone(), signature);
+		let stored = serde_json::to_string(&content).unwrap();
+		let content: Content = serde_json::from_str(&stored).unwrap();
+		assert!(content.verify(key));
+	}
 }
@@ -1,20 +1,56 @@
+use crate::util::is_default;
 use serde::{Deserialize, Serialize};
 use std::cmp::PartialEq;
 use std::collections::BTreeMap;
 use std::default::Default;
 
 #[derive(Serialize, Deserialize, Default, PartialEq, Clone)]
+#[serde(default)]
 pub struct UserContents {
-	archived: BTreeMap<String, usize>,
-	archived_before: usize,
-	cert_signers: BTreeMap<String, Vec<String>>,
-	cert_signers_pattern: String,
-	permission_rules: BTreeMap<String, PermissionRules>,
-	permissions: BTreeMap<String, PermissionRules>,
+	// Time before which the content of a user directory is archived, by directory
+	#[serde(skip_serializing_if = "is_default")]
+	pub archived: BTreeMap<String, usize>,
+	// Time before which the content of all user directories is archived
+	#[serde(skip_serializing_if = "is_default")]
+	pub archived_before: usize,
+	#[serde(skip_serializing_if = "is_default")]
+	pub cert_signers: BTreeMap<String, Vec<String>>,
+	#[serde(skip_serializing_if = "is_default")]
+	pub cert_signers_pattern: String,
+	#[serde(skip_serializing_if = "is_default")]
+	pub permission_rules: BTreeMap<String, PermissionRules>,
+	#[serde(skip_serializing_if = "is_default")]
+	pub permissions: BTreeMap<String, Permission>,
+}
+
+impl UserContents {
+	// Whether the content.json in a user directory, as of its modified time,
+	// was archived by the site owner and should be removed
+	pub fn is_archived(&self, user_dir: &str, modified: f64) -> bool {
+		let archived = self
+			.archived
+			.get(user_dir)
+			.map_or(0, |archived| *archived)
+			.max(self.archived_before);
+		archived > 0 && modified <= archived as f64
+	}
 }
 
 #[derive(Serialize, Deserialize, Default, PartialEq, Clone)]
+#[serde(default)]
 pub struct PermissionRules {
-	files_allowed: String,
-	max_size: usize,
+	#[serde(skip_serializing_if = "is_default")]
+	pub files_allowed: String,
+	#[serde(skip_serializing_if = "is_default")]
+	pub files_allowed_optional: String,
+	#[serde(skip_serializing_if = "is_default")]
+	pub max_size: usize,
+}
+
+// Permissions of a single user, `false` bans the user
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum Permission {
+	Rules(PermissionRules),
+	Banned(bool),
 }